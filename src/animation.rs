@@ -0,0 +1,186 @@
+use serde::Deserialize;
+use std::ops::{Add, Mul, Sub};
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation
+{
+    Linear,
+    // Passes through every key with tangents from the neighbouring keys, smooth but may overshoot.
+    CatmullRom,
+}
+
+impl Default for Interpolation
+{
+    fn default() -> Self
+    {
+        Interpolation::Linear
+    }
+}
+
+impl std::str::FromStr for Interpolation
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s
+        {
+            "linear" => Ok(Interpolation::Linear),
+            "catmull_rom" => Ok(Interpolation::CatmullRom),
+            _ => Err(format!("Unknown interpolation '{}'", s)),
+        }
+    }
+}
+
+// A value over time. Keys are sorted by time, the value is held before the first and after the last one.
+pub struct Track<T>
+{
+    keys: Vec<(f32, T)>,
+    pub interpolation: Interpolation,
+}
+
+impl<T> Track<T> where T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>
+{
+    pub fn constant(value: T) -> Track<T>
+    {
+        Track { keys: vec![(0f32, value)], interpolation: Interpolation::Linear }
+    }
+
+    // Expects at least one key, with increasing times.
+    pub fn new(keys: Vec<(f32, T)>, interpolation: Interpolation) -> Track<T>
+    {
+        Track { keys, interpolation }
+    }
+
+    pub fn is_animated(&self) -> bool
+    {
+        return self.keys.len() > 1;
+    }
+
+    pub fn times<'a>(&'a self) -> impl Iterator<Item = f32> + 'a
+    {
+        return self.keys.iter().map(|(time, _)| *time);
+    }
+
+    // Slope at key `i`, one sided at the ends.
+    fn tangent(&self, i: usize) -> T
+    {
+        let prev = i.saturating_sub(1);
+        let next = (i + 1).min(self.keys.len() - 1);
+        let (t0, v0) = self.keys[prev];
+        let (t1, v1) = self.keys[next];
+
+        return (v1 - v0) * (1f32 / (t1 - t0));
+    }
+
+    pub fn sample(&self, time: f32) -> T
+    {
+        let next = self.keys.iter().position(|(key_time, _)| *key_time > time).unwrap_or(self.keys.len());
+        if next == 0
+        {
+            return self.keys[0].1;
+        }
+        if next == self.keys.len()
+        {
+            return self.keys[next - 1].1;
+        }
+
+        let (t0, v0) = self.keys[next - 1];
+        let (t1, v1) = self.keys[next];
+        let s = (time - t0) / (t1 - t0);
+
+        return match self.interpolation
+        {
+            Interpolation::Linear => v0 + (v1 - v0) * s,
+            Interpolation::CatmullRom =>
+            {
+                // Cubic Hermite spline, the tangents are scaled from per time unit to the segment.
+                let (s2, s3) = (s * s, s * s * s);
+                let dt = t1 - t0;
+                v0 * (2f32 * s3 - 3f32 * s2 + 1f32)
+                    + self.tangent(next - 1) * ((s3 - 2f32 * s2 + s) * dt)
+                    + v1 * (3f32 * s2 - 2f32 * s3)
+                    + self.tangent(next) * ((s3 - s2) * dt)
+            },
+        };
+    }
+}
+
+// A key as written in the scene file.
+#[derive(Copy, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Keyframe<T>
+{
+    pub time: f32,
+    pub value: T,
+}
+
+// Track from scene file keys, or a constant when there are none. Fails when the times don't increase.
+pub fn track_from_keys<K: Copy, T>(keys: &[Keyframe<K>], fallback: T, interpolation: Interpolation, convert: impl Fn(K) -> T) -> Result<Track<T>, String>
+    where T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>
+{
+    if keys.is_empty()
+    {
+        return Ok(Track::constant(fallback));
+    }
+    if keys.windows(2).any(|pair| pair[0].time >= pair[1].time)
+    {
+        return Err("keyframe times must be increasing".to_owned());
+    }
+
+    return Ok(Track::new(keys.iter().map(|key| (key.time, convert(key.value))).collect(), interpolation));
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn assert_near(a: f32, b: f32)
+    {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn holds_the_end_values()
+    {
+        let track = Track::new(vec![(1f32, 10f32), (2f32, 30f32)], Interpolation::Linear);
+
+        assert_eq!(track.sample(0f32), 10f32);
+        assert_eq!(track.sample(5f32), 30f32);
+        assert_eq!(Track::constant(4f32).sample(100f32), 4f32);
+    }
+
+    #[test]
+    fn linear_interpolates_between_keys()
+    {
+        let track = Track::new(vec![(0f32, 0f32), (1f32, 10f32), (3f32, 30f32)], Interpolation::Linear);
+
+        assert_near(track.sample(0.5f32), 5f32);
+        assert_near(track.sample(2f32), 20f32);
+    }
+
+    #[test]
+    fn catmull_rom_passes_through_the_keys()
+    {
+        let track = Track::new(vec![(0f32, 0f32), (1f32, 10f32), (2f32, 5f32), (4f32, 8f32)], Interpolation::CatmullRom);
+
+        for &(time, value) in &[(0f32, 0f32), (1f32, 10f32), (2f32, 5f32), (4f32, 8f32)]
+        {
+            assert_near(track.sample(time), value);
+        }
+
+        // Keys on a line give the line.
+        let line = Track::new(vec![(0f32, 0f32), (1f32, 10f32), (2f32, 20f32)], Interpolation::CatmullRom);
+        assert_near(line.sample(0.5f32), 5f32);
+        assert_near(line.sample(1.25f32), 12.5f32);
+    }
+
+    #[test]
+    fn keys_need_increasing_times()
+    {
+        let keys = [Keyframe { time: 1f32, value: 0f32 }, Keyframe { time: 1f32, value: 2f32 }];
+        assert!(track_from_keys(&keys, 0f32, Interpolation::Linear, |v| v).is_err());
+    }
+}
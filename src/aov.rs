@@ -0,0 +1,184 @@
+use serde::Deserialize;
+use crate::application::Pixel;
+use crate::camera::Camera;
+use crate::output::*;
+use crate::scene::Hit;
+use crate::settings::{output_extension, suffixed_path};
+
+// Arbitrary output variables, per pixel data about the first surface seen through it.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Aov
+{
+    // Distance along the camera's forward axis.
+    Depth,
+    Position,
+    Normal,
+    GeometricNormal,
+    Uv,
+    MaterialId,
+    PrimitiveId,
+    Albedo,
+}
+
+impl std::str::FromStr for Aov
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s
+        {
+            "depth" => Ok(Aov::Depth),
+            "position" => Ok(Aov::Position),
+            "normal" => Ok(Aov::Normal),
+            "geometric_normal" => Ok(Aov::GeometricNormal),
+            "uv" => Ok(Aov::Uv),
+            "material_id" => Ok(Aov::MaterialId),
+            "primitive_id" => Ok(Aov::PrimitiveId),
+            "albedo" => Ok(Aov::Albedo),
+            _ => Err(format!("Unknown AOV '{}'", s)),
+        }
+    }
+}
+
+// An AOV accumulated into the r, g and b channels of a pixel buffer.
+pub struct AovBuffer
+{
+    pub aov: Aov,
+    pub pixels: Vec<Pixel>,
+}
+
+impl Aov
+{
+    pub fn name(&self) -> &'static str
+    {
+        return match self
+        {
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::Normal => "normal",
+            Aov::GeometricNormal => "geometric_normal",
+            Aov::Uv => "uv",
+            Aov::MaterialId => "material_id",
+            Aov::PrimitiveId => "primitive_id",
+            Aov::Albedo => "albedo",
+        };
+    }
+
+    pub fn channels(&self) -> &'static [&'static str]
+    {
+        return match self
+        {
+            Aov::Depth => &["Z"],
+            Aov::Position | Aov::Normal | Aov::GeometricNormal => &["X", "Y", "Z"],
+            Aov::Uv => &["U", "V"],
+            Aov::MaterialId | Aov::PrimitiveId => &["id"],
+            Aov::Albedo => &["R", "G", "B"],
+        };
+    }
+
+    // Depth and ids can't be averaged over the pixel, they keep the value of the first sample.
+    pub fn is_filtered(&self) -> bool
+    {
+        return match self
+        {
+            Aov::Depth | Aov::MaterialId | Aov::PrimitiveId => false,
+            _ => true,
+        };
+    }
+
+    // Only albedo is a color, the other AOVs are always saved as float since half can't even hold every id above 2048.
+    pub fn precision(&self, color_precision: ExrPrecision) -> ExrPrecision
+    {
+        return if *self == Aov::Albedo { color_precision } else { ExrPrecision::Float };
+    }
+
+    // Value for a camera ray with the given first hit. `albedo` is the surface color, or the environment on a miss.
+    pub fn evaluate(&self, hit: Option<&Hit>, albedo: glm::Vec3, camera: &Camera) -> glm::Vec3
+    {
+        let hit = match hit
+        {
+            Some(hit) => hit,
+            None => return match self
+            {
+                Aov::Depth => glm::vec3(std::f32::INFINITY, 0f32, 0f32),
+                Aov::MaterialId | Aov::PrimitiveId => glm::vec3(-1f32, 0f32, 0f32),
+                Aov::Albedo => albedo,
+                _ => glm::vec3(0f32, 0f32, 0f32),
+            },
+        };
+
+        return match self
+        {
+            Aov::Depth => glm::vec3(glm::dot(hit.pos - camera.pos, camera.forward), 0f32, 0f32),
+            Aov::Position => hit.pos,
+            Aov::Normal => hit.normal,
+            Aov::GeometricNormal => hit.geometric_normal,
+            Aov::Uv => glm::vec3(hit.uv.x, hit.uv.y, 0f32),
+            Aov::MaterialId => glm::vec3(hit.material_id as f32, 0f32, 0f32),
+            Aov::PrimitiveId => glm::vec3(hit.primitive_id as f32, 0f32, 0f32),
+            Aov::Albedo => albedo,
+        };
+    }
+}
+
+impl AovBuffer
+{
+    pub fn new(aov: Aov, width: u32, height: u32) -> AovBuffer
+    {
+        AovBuffer
+        {
+            aov,
+            pixels: vec![Pixel { r: 0f32, g: 0f32, b: 0f32, a: 0f32 }; (width * height) as usize],
+        }
+    }
+
+    fn channels(&self) -> Vec<(String, Vec<f32>)>
+    {
+        return self.aov.channels().iter().enumerate().map(|(i, name)|
+        {
+            let values = self.pixels.iter().map(|p| [p.r, p.g, p.b][i]).collect();
+            (name.to_string(), values)
+        }).collect();
+    }
+}
+
+// Saves the AOVs as layers of one EXR, or each to its own file with the AOV name appended to the path.
+// PFM holds a single image, so PFM output always gets a file per AOV.
+pub fn save_aovs(path: &str, buffers: &[&AovBuffer], width: u32, height: u32, multilayer: bool, precision: ExrPrecision) -> Result<(), String>
+{
+    let pfm = output_extension(path) == "pfm";
+
+    if multilayer && !pfm
+    {
+        let layers = buffers.iter().map(|buffer| ExrLayer
+        {
+            name: Some(buffer.aov.name().to_owned()),
+            precision: buffer.aov.precision(precision),
+            channels: buffer.channels(),
+        }).collect();
+        write_exr(path, width, height, layers)?;
+
+        println!("Saved AOVs: {}", path);
+        return Ok(());
+    }
+
+    for buffer in buffers
+    {
+        let aov_path = suffixed_path(path, &format!("_{}", buffer.aov.name()));
+        if pfm
+        {
+            let channels: Vec<Vec<f32>> = buffer.channels().into_iter().map(|(_, values)| values).collect();
+            write_pfm(&aov_path, width, height, &channels)?;
+        }
+        else
+        {
+            write_exr(&aov_path, width, height, vec![ExrLayer { name: None, precision: buffer.aov.precision(precision), channels: buffer.channels() }])?;
+        }
+
+        println!("Saved AOV: {}", aov_path);
+    }
+
+    return Ok(());
+}
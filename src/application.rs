@@ -1,8 +1,4 @@
-extern crate sdl2;
-extern crate gl;
-
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use crate::tonemap::DisplayTransform;
 
 pub struct Pixel
 {
@@ -35,31 +31,58 @@ pub fn f32vec_from_pixels(pixels: &Vec<Pixel>) -> Vec<f32>
     return input_img;
 }
 
-macro_rules! TRY_D
+// Running mean and variance of the luminance of a pixel's samples (Welford's algorithm).
+#[derive(Copy, Clone, Default)]
+pub struct PixelStats
+{
+    pub count: u32,
+    pub mean: f32,
+    pub m2: f32,
+}
+
+impl PixelStats
 {
-    ($e: expr) =>
+    pub fn add(&mut self, value: f32)
     {
-        match $e
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    pub fn variance(&self) -> f32
+    {
+        if self.count < 2
         {
-            Ok(_) => (),
-            Err(error) => println!("{}", error),
+            return 0f32;
         }
+
+        return self.m2 / (self.count - 1) as f32;
+    }
+
+    // Standard error of the mean relative to the square root of the brightness, roughly the error after a
+    // gamma curve so dark pixels don't need an unreasonable number of samples.
+    pub fn relative_error(&self) -> f32
+    {
+        if self.count == 0
+        {
+            return std::f32::MAX;
+        }
+
+        return (self.variance() / self.count as f32).sqrt() / self.mean.max(0.0001f32).sqrt();
     }
 }
 
+// CPU side render target. The preview window uploads this to a GL texture, the headless path only writes it to disk.
 pub struct RenderTexture
 {
     pub pixels: Vec<Pixel>,
     pub width: u32,
     pub height: u32,
-    pub id: u32,
 }
 
 pub struct Application
 {
-    pub sdl_context: sdl2::Sdl,
-    pub video_subsystem: sdl2::VideoSubsystem,
-    pub window: sdl2::video::Window,
     pub back_buffer: RenderTexture,
 }
 
@@ -72,186 +95,88 @@ impl Clone for Pixel
     }
 }
 
-pub trait Renderer
+impl RenderTexture
 {
-    fn init(&mut self, app: &mut Application);
-    fn render(&mut self, app: &mut Application);
+    pub fn new(width: u32, height: u32) -> RenderTexture
+    {
+        let pixels = vec![Pixel{r: 0f32, g: 0f32, b: 0f32, a: 0f32}; (width * height) as usize];
+
+        return RenderTexture{ pixels, width, height };
+    }
 }
 
-pub struct AppBuilder
+// Why a render session ended.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StopReason
 {
-    title: String,
-    width: u32,
-    height: u32,
-    running: bool,
+    // Every pixel has all its samples (or converged with adaptive sampling).
+    SampleTarget,
+    NoiseThreshold,
+    TimeLimit,
+    // The preview window was closed before any other condition was met.
+    Cancelled,
 }
 
-impl AppBuilder
+// Fly camera movement from the preview window since the last frame.
+#[derive(Copy, Clone)]
+pub struct CameraInput
 {
-    pub fn new(title: &str, width: u32, height: u32) -> AppBuilder
-    {
-        AppBuilder
-        {
-            title: title.to_owned(),
-            width,
-            height,
-            running: false,
-        }
-    }
-
-    pub fn start(&mut self, renderer_trait: &mut dyn Renderer)
-    {
-        let sdl_context = sdl2::init().unwrap();
-        let video_subsystem = sdl_context.video().unwrap();
-        let window = video_subsystem
-            .window(&self.title, self.width, self.height)
-            .hidden()
-            .opengl()
-            .resizable()
-            .build()
-            .unwrap();
-
-        // Init OpenGL
-        let _gl_context = window.gl_create_context().unwrap();
-        gl::load_with(|s| video_subsystem.gl_get_proc_address(s) as *const std::os::raw::c_void);
-
-        // Disable VSync
-        TRY_D!(video_subsystem.gl_set_swap_interval(0));
-
-        let mut event_pump = sdl_context.event_pump().unwrap();
-
-        /*
-        Create Upload Frame Buffer
-        */
-        let back_buffer_texture = self.create_texture(self.width, self.height);
-        let mut upload_frame_buffer: u32 = 0;
-        self.create_fb(&mut upload_frame_buffer);
-
-        let mut app = Application { sdl_context, video_subsystem, window, back_buffer: back_buffer_texture };
-
-        renderer_trait.init(&mut app);
-
-        self.show(&mut app);
-
-        self.running = true;
-        while self.running
-        {
-            self.parse_events(&mut event_pump);
-
-            renderer_trait.render(&mut app);
-
-            self.update_back_buffer(&app.back_buffer);
-            self.copy_back_to_front(&app.back_buffer);
-
-            app.window.gl_swap_window();
-        }
-    }
-
-    fn copy_back_to_front(&self, texture: &RenderTexture)
-    {
-        unsafe
-        {
-            // Bind source texture
-            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, texture.id);
-            gl::FramebufferTexture(gl::READ_FRAMEBUFFER, gl::COLOR_ATTACHMENT0, texture.id, 0);
-
-            // Bind current back buffer.
-            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, gl::FRONT);
-            gl::FramebufferTexture(gl::DRAW_FRAMEBUFFER, gl::COLOR_ATTACHMENT1, gl::FRONT, 0);
-
-            // Copy texture to back buffer.
-            gl::BlitFramebuffer(0, 0, texture.width as i32, texture.height as i32,
-                                0, 0, self.width as i32, self.height as i32,
-                                gl::COLOR_BUFFER_BIT, gl::NEAREST);
-
-            // Unbind the color attachments.
-            gl::FramebufferTexture(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT1, 0, 0);
-            gl::FramebufferTexture(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, 0, 0);
-        }
-    }
+    // Along the camera's right, up and forward axes, in scene units.
+    pub translation: glm::Vec3,
+    // Radians, positive yaw turns left around the up vector and positive pitch looks up.
+    pub yaw: f32,
+    pub pitch: f32,
+    // Scroll steps, positive zooms in or moves the focus further away.
+    pub zoom: f32,
+    pub focus: f32,
+}
 
-    fn update_back_buffer(&self, texture: &RenderTexture)
+impl Default for CameraInput
+{
+    fn default() -> Self
     {
-        unsafe
-        {
-            gl::BindTexture(gl::TEXTURE_2D, texture.id);
-            gl::TexSubImage2D(gl::TEXTURE_2D, 0, 0, 0, texture.width as i32, texture.height as i32,
-                              gl::BGRA, gl::FLOAT, texture.pixels.as_ptr() as *const std::os::raw::c_void);
-            gl::BindTexture(gl::TEXTURE_2D, 0);
-        }
+        CameraInput { translation: glm::vec3(0f32, 0f32, 0f32), yaw: 0f32, pitch: 0f32, zoom: 0f32, focus: 0f32 }
     }
+}
 
-    fn create_fb(&self, id: &mut u32)
+impl CameraInput
+{
+    pub fn is_empty(&self) -> bool
     {
-        unsafe
-        {
-            gl::GenFramebuffers(1, id);
-        }
-    }
-
-    fn create_texture(&self, width: u32, height: u32) -> RenderTexture
-    {
-        let mut id: u32 = 0;
-        let pixels = vec![Pixel{r: 0f32, g: 0f32, b: 0f32, a: 0f32}; (width * height) as usize];
-
-        unsafe
-        {
-            gl::GenTextures(1, &mut id);
-
-            gl::ActiveTexture(gl::TEXTURE0);
-            gl::BindTexture(gl::TEXTURE_2D, id);
-            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA as i32, self.width as i32, self.height as i32,
-                           0, gl::BGRA, gl::FLOAT, pixels.as_ptr() as *const std::os::raw::c_void);
-            gl::BindTexture(gl::TEXTURE_2D, 0);
-        }
-
-        return RenderTexture{ pixels, width, height, id };
+        return self.translation == glm::vec3(0f32, 0f32, 0f32) && self.yaw == 0f32 && self.pitch == 0f32 && self.zoom == 0f32 && self.focus == 0f32;
     }
+}
 
-    fn parse_events(&mut self, event_pump: &mut sdl2::EventPump)
-    {
-        use sdl2::event::WindowEvent;
+pub trait Renderer
+{
+    fn init(&mut self, app: &mut Application);
+    fn render(&mut self, app: &mut Application);
+    // The preview window changed size, the preview restarts at a size that fits it.
+    fn resize(&mut self, app: &mut Application, width: u32, height: u32);
+    // Moves the camera and restarts the accumulation.
+    fn move_camera(&mut self, app: &mut Application, input: &CameraInput);
+    // How the preview shows the linear back buffer.
+    fn display_transform(&self) -> DisplayTransform;
+    // None while the render should continue.
+    fn stop_reason(&self) -> Option<StopReason>;
+    // Writes the outputs and returns the exit status of the process.
+    fn finish(&mut self, app: &mut Application, reason: StopReason) -> i32;
+}
 
-        for event in event_pump.poll_iter()
-        {
-            match event
-            {
-                Event::Quit {..} |
-                Event::KeyDown { keycode: Some(Keycode::Escape), .. } =>
-                {
-                    self.running = false;
-                },
-                Event::Window { win_event: WindowEvent::Resized(width, height), ..} =>
-                {
-                    self.resize_event(width, height);
-                },
-                _ => {}
-            }
-        }
-    }
+// Renders without a window or GL context until the renderer reports it is finished, then lets it write its outputs.
+pub fn run_headless(width: u32, height: u32, renderer_trait: &mut dyn Renderer) -> i32
+{
+    let mut app = Application { back_buffer: RenderTexture::new(width, height) };
 
-    fn resize_event(&mut self, width: i32, height: i32)
-    {
-        if width > 0 && height > 0
-        {
-            self.width = width as u32;
-            self.height = height as u32;
-        }
-    }
+    renderer_trait.init(&mut app);
 
-    fn clear(&self)
+    loop
     {
-        unsafe
+        if let Some(reason) = renderer_trait.stop_reason()
         {
-            gl::Clear(gl::COLOR_BUFFER_BIT);
-            gl::ClearColor(0.3, 0.3, 0.5, 1.0);
+            return renderer_trait.finish(&mut app, reason);
         }
-    }
 
-    fn show(&self, app: &mut Application)
-    {
-        self.clear();
-        app.window.gl_swap_window();
-        app.window.show();
+        renderer_trait.render(&mut app);
     }
 }
@@ -0,0 +1,77 @@
+// Little endian reader for the checkpoint and film files.
+pub struct Reader<'a>
+{
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a>
+{
+    pub fn new(data: &'a [u8]) -> Reader<'a>
+    {
+        return Reader { data, pos: 0 };
+    }
+
+    pub fn bytes(&mut self, count: usize) -> Result<&'a [u8], String>
+    {
+        if count > self.remaining()
+        {
+            return Err("unexpected end of file".to_owned());
+        }
+
+        let bytes = &self.data[self.pos..self.pos + count];
+        self.pos += count;
+        return Ok(bytes);
+    }
+
+    pub fn remaining(&self) -> usize
+    {
+        return self.data.len() - self.pos;
+    }
+
+    // Checks that `count` records of `size` bytes fit in the rest of the file before anything is allocated for them.
+    pub fn records(&self, count: u64, size: usize) -> Result<usize, String>
+    {
+        match count.checked_mul(size as u64)
+        {
+            Some(length) if length <= self.remaining() as u64 => Ok(count as usize),
+            _ => Err(format!("{} records don't fit in the remaining {} bytes", count, self.remaining())),
+        }
+    }
+
+    // Number of pixels of a `width` x `height` image stored in `size` bytes each, which must be exactly the rest of the file.
+    pub fn pixels(&self, width: u32, height: u32, size: usize) -> Result<usize, String>
+    {
+        let count = self.records(width as u64 * height as u64, size)?;
+        if count * size != self.remaining()
+        {
+            return Err(format!("{} bytes of pixel data don't match the resolution {}x{}", self.remaining(), width, height));
+        }
+
+        return Ok(count);
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String>
+    {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.bytes(4)?);
+        return Ok(u32::from_le_bytes(bytes));
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String>
+    {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        return Ok(u64::from_le_bytes(bytes));
+    }
+
+    pub fn f32(&mut self) -> Result<f32, String>
+    {
+        return Ok(f32::from_bits(self.u32()?));
+    }
+
+    pub fn f64(&mut self) -> Result<f64, String>
+    {
+        return Ok(f64::from_bits(self.u64()?));
+    }
+}
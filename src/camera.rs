@@ -1,5 +1,45 @@
-use std::f32::consts::{FRAC_PI_4, FRAC_PI_2};
+use std::f32::consts::{FRAC_PI_4, FRAC_PI_2, PI};
+use crate::animation::Track;
+use crate::application::CameraInput;
 
+// How much of the scene the camera sees. Angles are in degrees, lengths in millimeters.
+#[derive(Copy, Clone, Debug)]
+pub enum FieldOfView
+{
+    Vertical(f32),
+    Horizontal(f32),
+    // The sensor width spans the image width, like the horizontal sensor fit of DCC tools.
+    FocalLength { focal_length: f32, sensor_width: f32 },
+}
+
+// Lens opening. A diameter is in scene units, an f-stop is converted through the focal length with scene units
+// taken as meters.
+#[derive(Copy, Clone, Debug)]
+pub enum Aperture
+{
+    Pinhole,
+    Diameter(f32),
+    FStop(f32),
+}
+
+// How camera space directions map onto the image.
+#[derive(Copy, Clone, Debug)]
+pub enum Projection
+{
+    // Pinhole or thin lens, using the camera's field of view.
+    Perspective,
+    // Parallel rays, the image spans `height` scene units vertically.
+    Orthographic { height: f32 },
+    // Equidistant fisheye, a circle as high as the image covers `fov` radians. Pixels outside it stay black.
+    Fisheye { fov: f32 },
+    // Full 360 by 180 degree panorama, the image center looks forward.
+    Equirectangular,
+}
+
+// Full frame, used to relate angular fields of view to a focal length.
+pub static DEFAULT_SENSOR_WIDTH: f32 = 36f32;
+
+#[derive(Debug)]
 pub struct Camera
 {
     pub pos: glm::Vec3,
@@ -10,6 +50,9 @@ pub struct Camera
     pub lens_radius: f32,
     pub forward: glm::Vec3,
     pub focal_dist: f32,
+    // Millimeters.
+    pub sensor_width: f32,
+    pub projection: Projection,
     pub viewport_width: u32,
     pub viewport_height: u32,
 }
@@ -21,6 +64,184 @@ impl Clone for Camera {
     }
 }
 
+impl Camera
+{
+    // Camera looking along `forward`. `up` only needs to be roughly up, the basis is made orthonormal.
+    pub fn look_to(eye: glm::Vec3, forward: glm::Vec3, up: glm::Vec3, fov: FieldOfView, width: u32, height: u32) -> Camera
+    {
+        // Right handed: a camera looking down +z with +y up has +x on the left of the image.
+        let forward = glm::normalize(forward);
+        let right = glm::normalize(glm::cross(forward, up));
+        let up = glm::cross(right, forward);
+
+        let aspect_ratio = width as f32 / height as f32;
+        let (half_fov, sensor_width) = match fov
+        {
+            FieldOfView::Vertical(degrees) => ((0.5f32 * degrees).to_radians().tan(), DEFAULT_SENSOR_WIDTH),
+            FieldOfView::Horizontal(degrees) => ((0.5f32 * degrees).to_radians().tan() / aspect_ratio, DEFAULT_SENSOR_WIDTH),
+            FieldOfView::FocalLength { focal_length, sensor_width } => (sensor_width / (2f32 * focal_length) / aspect_ratio, sensor_width),
+        };
+
+        Camera
+        {
+            pos: eye,
+            aspect_ratio,
+            up,
+            half_fov,
+            right,
+            lens_radius: 0f32,
+            forward,
+            focal_dist: 10f32,
+            sensor_width,
+            projection: Projection::Perspective,
+            viewport_width: width,
+            viewport_height: height,
+        }
+    }
+
+    // Camera at `eye` looking at `target`, focused on the target.
+    pub fn look_at(eye: glm::Vec3, target: glm::Vec3, up: glm::Vec3, fov: FieldOfView, width: u32, height: u32) -> Camera
+    {
+        let mut camera = Camera::look_to(eye, target - eye, up, fov, width, height);
+        camera.focal_dist = glm::length(target - eye);
+
+        return camera;
+    }
+
+    // Focal length in millimeters that gives the field of view on the sensor.
+    pub fn focal_length(&self) -> f32
+    {
+        return self.sensor_width / (2f32 * self.half_fov * self.aspect_ratio);
+    }
+
+    pub fn set_aperture(&mut self, aperture: Aperture)
+    {
+        self.lens_radius = match aperture
+        {
+            Aperture::Pinhole => 0f32,
+            Aperture::Diameter(diameter) => 0.5f32 * diameter,
+            Aperture::FStop(f_stop) => 0.001f32 * self.focal_length() / (2f32 * f_stop),
+        };
+    }
+
+    pub fn set_focus_distance(&mut self, distance: f32)
+    {
+        self.focal_dist = distance;
+    }
+
+    pub fn set_projection(&mut self, projection: Projection)
+    {
+        self.projection = projection;
+    }
+}
+
+// Where an animated camera looks.
+pub enum ViewTrack
+{
+    Target(Track<glm::Vec3>),
+    Forward(Track<glm::Vec3>),
+}
+
+// The choices of `FieldOfView` with an animated value.
+pub enum FieldOfViewTrack
+{
+    Vertical(Track<f32>),
+    Horizontal(Track<f32>),
+    FocalLength { focal_length: Track<f32>, sensor_width: f32 },
+}
+
+// A camera with keyframed position, view, field of view and focus. `at` gives the camera a ray at that time sees.
+pub struct CameraAnimation
+{
+    pub position: Track<glm::Vec3>,
+    pub view: ViewTrack,
+    pub up: glm::Vec3,
+    pub fov: FieldOfViewTrack,
+    // Millimeters.
+    pub sensor_width: f32,
+    // The distance to the target (or 10 without one) when not given.
+    pub focal_distance: Option<Track<f32>>,
+    pub aperture: Aperture,
+    pub projection: Projection,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CameraAnimation
+{
+    pub fn at(&self, time: f32) -> Camera
+    {
+        let fov = match &self.fov
+        {
+            FieldOfViewTrack::Vertical(track) => FieldOfView::Vertical(track.sample(time)),
+            FieldOfViewTrack::Horizontal(track) => FieldOfView::Horizontal(track.sample(time)),
+            FieldOfViewTrack::FocalLength { focal_length, sensor_width } => FieldOfView::FocalLength { focal_length: focal_length.sample(time), sensor_width: *sensor_width },
+        };
+
+        let position = self.position.sample(time);
+        let mut camera = match &self.view
+        {
+            ViewTrack::Target(target) => Camera::look_at(position, target.sample(time), self.up, fov, self.width, self.height),
+            ViewTrack::Forward(forward) => Camera::look_to(position, forward.sample(time), self.up, fov, self.width, self.height),
+        };
+
+        // An angular field of view still needs a sensor to relate an f-stop to.
+        camera.sensor_width = self.sensor_width;
+        camera.set_projection(self.projection);
+        if let Some(distance) = &self.focal_distance
+        {
+            camera.set_focus_distance(distance.sample(time));
+        }
+        camera.set_aperture(self.aperture);
+
+        return camera;
+    }
+
+    // Replaces the animation with the camera at `time` moved by the fly controls. Zooming narrows the field
+    // of view (or the orthographic height) by a tenth per step, focusing moves the focus by a tenth per step.
+    pub fn fly(&mut self, time: f32, input: &CameraInput)
+    {
+        let camera = self.at(time);
+
+        let position = camera.pos + camera.right * input.translation.x + camera.up * input.translation.y + camera.forward * input.translation.z;
+
+        let mut forward = rotate(camera.forward, self.up, input.yaw);
+        let pitched = rotate(forward, glm::normalize(glm::cross(forward, self.up)), input.pitch);
+        // Stops short of looking straight up or down, where the up vector no longer defines a basis.
+        if glm::dot(pitched, glm::normalize(self.up)).abs() < 0.99f32
+        {
+            forward = pitched;
+        }
+
+        let zoom = 0.9f32.powf(input.zoom);
+        self.fov = match &self.fov
+        {
+            FieldOfViewTrack::Vertical(track) => FieldOfViewTrack::Vertical(Track::constant((track.sample(time) * zoom).max(1f32).min(179f32))),
+            FieldOfViewTrack::Horizontal(track) => FieldOfViewTrack::Horizontal(Track::constant((track.sample(time) * zoom).max(1f32).min(179f32))),
+            FieldOfViewTrack::FocalLength { focal_length, sensor_width } =>
+                FieldOfViewTrack::FocalLength { focal_length: Track::constant(focal_length.sample(time) / zoom), sensor_width: *sensor_width },
+        };
+        self.projection = match self.projection
+        {
+            Projection::Orthographic { height } => Projection::Orthographic { height: height * zoom },
+            Projection::Fisheye { fov } => Projection::Fisheye { fov: (fov * zoom).min(2f32 * PI) },
+            projection => projection,
+        };
+
+        self.position = Track::constant(position);
+        self.view = ViewTrack::Forward(Track::constant(forward));
+        self.focal_distance = Some(Track::constant(camera.focal_dist * 1.1f32.powf(input.focus)));
+    }
+}
+
+// Rodrigues' rotation of `v` around the unit `axis`.
+fn rotate(v: glm::Vec3, axis: glm::Vec3, angle: f32) -> glm::Vec3
+{
+    let axis = glm::normalize(axis);
+    let (sin, cos) = angle.sin_cos();
+    return v * cos + glm::cross(axis, v) * sin + axis * (glm::dot(axis, v) * (1f32 - cos));
+}
+
 fn camera_to_world(v: glm::Vec3, right: glm::Vec3, up: glm::Vec3, forward: glm::Vec3) -> glm::Vec3
 {
     let v = glm::vec3(v.x, v.y, v.z);
@@ -54,29 +275,68 @@ fn sample_disk_concentric(u: glm::Vec2) -> glm::Vec2
     }
 }
 
-pub fn generate_camera_ray(pixel_uv: glm::Vec2, lens_uv: glm::Vec2, p: &mut glm::Vec3, wo: &mut glm::Vec3, camera: &Camera)
+// Moves a camera space ray onto a point of the lens, keeping the point where it meets the focus plane.
+fn thin_lens(p_camera: glm::Vec3, wo_camera: glm::Vec3, lens_uv: glm::Vec2, camera: &Camera) -> (glm::Vec3, glm::Vec3)
+{
+    if camera.lens_radius <= 0f32
+    {
+        return (p_camera, wo_camera);
+    }
+
+    let t_focus = (camera.focal_dist - p_camera.z) / wo_camera.z;
+    let p_focus = p_camera + wo_camera * t_focus;
+    let lens = sample_disk_concentric(lens_uv) * camera.lens_radius;
+    let p_lens = p_camera + glm::vec3(lens.x, lens.y, 0f32);
+
+    return (p_lens, glm::normalize(p_focus - p_lens));
+}
+
+// Returns false when the pixel has no ray, outside the image circle of a fisheye.
+pub fn generate_camera_ray(pixel_uv: glm::Vec2, lens_uv: glm::Vec2, p: &mut glm::Vec3, wo: &mut glm::Vec3, camera: &Camera) -> bool
 {
     let up = camera.up;
     let right = camera.right;
     let forward = camera.forward;
 
-    let tx = camera.half_fov * camera.aspect_ratio * (2f32 * pixel_uv.x - 1f32);
-    let ty = camera.half_fov * (2f32 * pixel_uv.y - 1f32);
-
-    let mut p_camera  = glm::vec3(0f32, 0f32, 0f32);
-    let mut wo_camera = glm::normalize(glm::vec3(tx, ty, 1.0));
+    let ndc = glm::vec2(2f32 * pixel_uv.x - 1f32, 2f32 * pixel_uv.y - 1f32);
+    let origin = glm::vec3(0f32, 0f32, 0f32);
 
-    // Depth of Field
-    if camera.lens_radius > 0.0
+    let (p_camera, wo_camera) = match camera.projection
     {
-        let t_focus = camera.focal_dist / wo_camera.z;
-        let p_focus = wo_camera * t_focus;
-        let temp = sample_disk_concentric(lens_uv) * camera.lens_radius;
-        p_camera.x = temp.x;
-        p_camera.y = temp.y;
-        wo_camera = glm::normalize(p_focus - p_camera);
-    }
+        Projection::Perspective =>
+        {
+            let tx = camera.half_fov * camera.aspect_ratio * ndc.x;
+            let ty = camera.half_fov * ndc.y;
+            thin_lens(origin, glm::normalize(glm::vec3(tx, ty, 1f32)), lens_uv, camera)
+        },
+        Projection::Orthographic { height } =>
+        {
+            let p_camera = glm::vec3(0.5f32 * height * camera.aspect_ratio * ndc.x, 0.5f32 * height * ndc.y, 0f32);
+            thin_lens(p_camera, glm::vec3(0f32, 0f32, 1f32), lens_uv, camera)
+        },
+        Projection::Fisheye { fov } =>
+        {
+            let xy = glm::vec2(ndc.x * camera.aspect_ratio, ndc.y);
+            let r = glm::length(xy);
+            if r > 1f32
+            {
+                return false;
+            }
+
+            let theta = r * 0.5f32 * fov;
+            let phi = xy.y.atan2(xy.x);
+            (origin, glm::vec3(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos()))
+        },
+        Projection::Equirectangular =>
+        {
+            let phi = ndc.x * PI;
+            let theta = ndc.y * FRAC_PI_2;
+            (origin, glm::vec3(phi.sin() * theta.cos(), theta.sin(), phi.cos() * theta.cos()))
+        },
+    };
 
     *p  = camera_to_world(p_camera, right, up, forward) + camera.pos;
     *wo = camera_to_world(wo_camera, right, up, forward);
-}
\ No newline at end of file
+
+    return true;
+}
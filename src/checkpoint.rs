@@ -0,0 +1,202 @@
+use crate::application::PixelStats;
+use crate::binary::Reader;
+use crate::film::FilmPixel;
+
+// Layout, all values little endian:
+//   magic "RGLCKPT\0", version u32, fingerprint u64, width u32, height u32, accumulation_idx u32,
+//   active_pixels u64, noise_level f32, elapsed seconds f64,
+//   then per pixel: weighted sum r, g, b f64, weight f64, sample count u32, mean f32, m2 f32.
+// Samplers are pure functions of the seed, pixel and sample index, so the fingerprint (which covers the
+// seed and sampler) and the per pixel sample counts are all the sampler state there is.
+static MAGIC: &[u8; 8] = b"RGLCKPT\0";
+static VERSION: u32 = 2;
+static PIXEL_SIZE: usize = 44;
+
+// FNV-1a, unlike the std hasher it is stable between Rust versions.
+pub fn fingerprint(bytes: &[u8]) -> u64
+{
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for &byte in bytes
+    {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    return hash;
+}
+
+pub struct CheckpointHeader
+{
+    // RenderSettings::render_fingerprint of the render that wrote the checkpoint.
+    pub fingerprint: u64,
+    pub width: u32,
+    pub height: u32,
+    pub accumulation_idx: u32,
+    pub active_pixels: u64,
+    pub noise_level: f32,
+    pub elapsed: f64,
+}
+
+// Written to a temporary file first, so a crash while saving leaves the previous checkpoint intact.
+pub fn save_checkpoint(path: &str, header: &CheckpointHeader, film: &[FilmPixel], stats: &[PixelStats]) -> Result<(), String>
+{
+    let mut data = Vec::with_capacity(64 + film.len() * PIXEL_SIZE);
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&VERSION.to_le_bytes());
+    data.extend_from_slice(&header.fingerprint.to_le_bytes());
+    data.extend_from_slice(&header.width.to_le_bytes());
+    data.extend_from_slice(&header.height.to_le_bytes());
+    data.extend_from_slice(&header.accumulation_idx.to_le_bytes());
+    data.extend_from_slice(&header.active_pixels.to_le_bytes());
+    data.extend_from_slice(&header.noise_level.to_bits().to_le_bytes());
+    data.extend_from_slice(&header.elapsed.to_bits().to_le_bytes());
+
+    for (pixel, stats) in film.iter().zip(stats)
+    {
+        for value in &[pixel.sum[0], pixel.sum[1], pixel.sum[2], pixel.weight]
+        {
+            data.extend_from_slice(&value.to_bits().to_le_bytes());
+        }
+        data.extend_from_slice(&stats.count.to_le_bytes());
+        data.extend_from_slice(&stats.mean.to_bits().to_le_bytes());
+        data.extend_from_slice(&stats.m2.to_bits().to_le_bytes());
+    }
+
+    let temp_path = format!("{}.tmp", path);
+    std::fs::write(&temp_path, &data).map_err(|e| format!("Failed to write checkpoint '{}': {}", temp_path, e))?;
+    std::fs::rename(&temp_path, path).map_err(|e| format!("Failed to write checkpoint '{}': {}", path, e))?;
+
+    return Ok(());
+}
+
+// Fails unless the checkpoint was written by a render of the given size and fingerprint.
+pub fn load_checkpoint(path: &str, fingerprint: u64, width: u32, height: u32) -> Result<(CheckpointHeader, Vec<FilmPixel>, Vec<PixelStats>), String>
+{
+    let data = std::fs::read(path).map_err(|e| format!("Failed to read checkpoint '{}': {}", path, e))?;
+    let mut reader = Reader::new(&data);
+    let error = |e: String| format!("Invalid checkpoint '{}': {}", path, e);
+
+    if reader.bytes(MAGIC.len()).map_err(error)? != MAGIC
+    {
+        return Err(format!("'{}' is not a checkpoint", path));
+    }
+
+    let version = reader.u32().map_err(error)?;
+    if version != VERSION
+    {
+        return Err(format!("Checkpoint '{}' has version {}, expected {}", path, version, VERSION));
+    }
+
+    let header = CheckpointHeader
+    {
+        fingerprint: reader.u64().map_err(error)?,
+        width: reader.u32().map_err(error)?,
+        height: reader.u32().map_err(error)?,
+        accumulation_idx: reader.u32().map_err(error)?,
+        active_pixels: reader.u64().map_err(error)?,
+        noise_level: reader.f32().map_err(error)?,
+        elapsed: reader.f64().map_err(error)?,
+    };
+
+    if header.width != width || header.height != height
+    {
+        return Err(format!("Checkpoint '{}' is {}x{}, the render is {}x{}", path, header.width, header.height, width, height));
+    }
+    if header.fingerprint != fingerprint
+    {
+        return Err(format!("Checkpoint '{}' was rendered with a different scene, camera or settings", path));
+    }
+
+    let count = reader.pixels(header.width, header.height, PIXEL_SIZE).map_err(error)?;
+    let mut film = Vec::with_capacity(count);
+    let mut stats = Vec::with_capacity(count);
+    for _ in 0..count
+    {
+        film.push(FilmPixel
+        {
+            sum: [reader.f64().map_err(error)?, reader.f64().map_err(error)?, reader.f64().map_err(error)?],
+            weight: reader.f64().map_err(error)?,
+        });
+        stats.push(PixelStats
+        {
+            count: reader.u32().map_err(error)?,
+            mean: reader.f32().map_err(error)?,
+            m2: reader.f32().map_err(error)?,
+        });
+    }
+
+    return Ok((header, film, stats));
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::test_util::temp_path;
+
+    fn test_checkpoint() -> (CheckpointHeader, Vec<FilmPixel>, Vec<PixelStats>)
+    {
+        let header = CheckpointHeader { fingerprint: 0x1234, width: 3, height: 2, accumulation_idx: 17, active_pixels: 4, noise_level: 0.25, elapsed: 12.5 };
+        let film = (0..6).map(|i| FilmPixel { sum: [i as f64, 0.5 * i as f64, -1f64], weight: 1f64 + i as f64 }).collect();
+        let stats = (0..6).map(|i| PixelStats { count: i, mean: 0.1 * i as f32, m2: 0.5 }).collect();
+        return (header, film, stats);
+    }
+
+    #[test]
+    fn save_and_load_round_trip()
+    {
+        let path = temp_path("round_trip.ckpt");
+        let (header, film, stats) = test_checkpoint();
+        save_checkpoint(&path, &header, &film, &stats).unwrap();
+
+        let (loaded, loaded_film, loaded_stats) = load_checkpoint(&path, 0x1234, 3, 2).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((loaded.accumulation_idx, loaded.active_pixels, loaded.noise_level, loaded.elapsed), (17, 4, 0.25, 12.5));
+        for (a, b) in film.iter().zip(&loaded_film)
+        {
+            assert_eq!((a.sum, a.weight), (b.sum, b.weight));
+        }
+        for (a, b) in stats.iter().zip(&loaded_stats)
+        {
+            assert_eq!((a.count, a.mean, a.m2), (b.count, b.mean, b.m2));
+        }
+    }
+
+    #[test]
+    fn rejects_another_fingerprint_or_size()
+    {
+        let path = temp_path("mismatch.ckpt");
+        let (header, film, stats) = test_checkpoint();
+        save_checkpoint(&path, &header, &film, &stats).unwrap();
+
+        let other_fingerprint = load_checkpoint(&path, 0x4321, 3, 2);
+        let other_size = load_checkpoint(&path, 0x1234, 2, 3);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(other_fingerprint.is_err());
+        assert!(other_size.is_err());
+    }
+
+    #[test]
+    fn rejects_corrupt_files()
+    {
+        let path = temp_path("corrupt.ckpt");
+        let (header, film, stats) = test_checkpoint();
+        save_checkpoint(&path, &header, &film, &stats).unwrap();
+        let data = std::fs::read(&path).unwrap();
+
+        std::fs::write(&path, &data[..data.len() - 1]).unwrap();
+        let truncated = load_checkpoint(&path, 0x1234, 3, 2);
+
+        // A header claiming a huge image fails before anything is allocated for it.
+        let mut huge = data.clone();
+        huge[20..28].copy_from_slice(&[0xff; 8]);
+        std::fs::write(&path, &huge).unwrap();
+        let oversized = load_checkpoint(&path, 0x1234, std::u32::MAX, std::u32::MAX);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(truncated.is_err());
+        assert!(oversized.is_err());
+    }
+}
@@ -0,0 +1,421 @@
+use crate::settings::*;
+use crate::sampler::SamplerType;
+use crate::tile::TileOrder;
+use crate::aov::Aov;
+use crate::output::ExrPrecision;
+use crate::tonemap::{DisplayTransform, ToneMapper};
+use crate::filter::FilterType;
+
+pub static USAGE: &str = "\
+Usage: rust_gl [OPTIONS] [SCENE]
+       rust_gl merge [OPTIONS] <FILM>...
+
+Renders SCENE (a TOML scene description, defaults to test.toml). Options override the
+[render] section of the scene file.
+
+Options:
+    --headless                  Render without a window and exit when done
+    --keep-open                 Keep the preview window open after the render finished
+    --fly-speed <UNITS>         Preview fly camera speed in scene units per second
+    --resolution <WxH>          Output resolution, e.g. 1280x720
+    --resolution-scale <S>      Preview at S times the window size (0 < S <= 1), outputs keep the full resolution
+    --spp <N>                   Samples per pixel
+    --max-depth <N>             Maximum path length
+    --min-depth <N>             Bounces before Russian roulette may terminate a path
+    --max-diffuse-depth <N>     Maximum number of diffuse bounces
+    --max-specular-depth <N>    Maximum number of specular bounces
+    --time-limit <SECONDS>      Stop the render after this much wall clock time
+    --noise-threshold <E>       Stop the render once the mean relative pixel error is below E
+    --checkpoint <PATH>         Periodically save the render state so it can be resumed
+    --checkpoint-interval <SECONDS>
+                                Time between checkpoints
+    --resume <PATH>             Continue the render saved in a checkpoint of the same scene and settings
+    --film-output <PATH>        Save the unnormalized film for `rust_gl merge`
+    --threads <N>               Number of render threads
+    --tile-size <N>             Edge length of the render tiles in pixels
+    --tile-order <ORDER>        Tile order: scanline, spiral or hilbert
+    --samples-per-pass <N>      Samples rendered per pixel before the preview is updated
+    --adaptive / --no-adaptive  Stop sampling pixels once they are converged, --spp is the maximum
+    --adaptive-threshold <E>    Relative error at which a pixel counts as converged
+    --adaptive-min-samples <N>  Samples taken before a pixel may count as converged
+    --sample-count-output <PATH>
+                                Save the number of samples per pixel as a grayscale image
+    --seed <N>                  Random seed, renders with the same seed are identical
+    --sampler <NAME>            Sample generator: independent, stratified, sobol, halton or blue_noise
+    --output <PATH>             Output image, the format is taken from the extension: png,
+                                or exr and pfm for linear float output
+    --exr-precision <TYPE>      Sample type of EXR color channels: half or float
+    --tone-mapper <NAME>        Tone mapping of the preview and PNG output: linear, reinhard,
+                                aces, agx or hable
+    --exposure <EV>             Exposure adjustment in stops before tone mapping
+    --white-point <L>           Radiance that maps to white with the reinhard tone mapper
+    --denoise / --no-denoise    Denoise the final image with Open Image Denoise
+    --extended-denoising / --no-extended-denoising
+                                Feed albedo and normal buffers to the denoiser
+    --gbuffer-samples <N>       Samples used for the AOVs and the denoiser albedo and normal buffers
+    --aovs <LIST>               Comma separated AOVs to save: depth, position, normal,
+                                geometric_normal, uv, material_id, primitive_id, albedo
+    --aov-output <PATH>         EXR or PFM file the AOVs are saved to, PFM gets a file per AOV
+    --aov-multilayer / --no-aov-multilayer
+                                Save all AOVs as layers of one file instead of a file each
+    --jitter-aa / --no-jitter-aa
+                                Jitter camera rays within the pixel
+    --filter <NAME>             Reconstruction filter: box, tent, gaussian, mitchell or blackman_harris
+    --filter-radius <PIXELS>    Filter radius, defaults to 0.5 for box, 1 for tent, 1.5 for gaussian
+                                and 2 for the others
+    --frames <N[..M]>           Render frame N, or frames N to M as a numbered image sequence
+                                (a run of '#' in the output path is replaced by the frame number)
+    --frame-rate <FPS>          Frames per second, frame N is rendered at N / FPS seconds
+    --shutter-open <SECONDS>    Time after the frame start the shutter opens
+    --shutter-close <SECONDS>   Time after the frame start the shutter closes, moving objects blur
+                                over the interval
+    -h, --help                  Print this message
+
+Preview controls (moving the camera restarts the render):
+    W A S D                     Move forward, left, back and right
+    Q E                         Move down and up
+    Shift                       Move faster
+    Right mouse button          Look around
+    Mouse wheel                 Zoom
+    Ctrl + mouse wheel          Move the focus distance
+
+Exit status:
+    0  the render reached its sample count or noise threshold
+    1  loading the scene or writing an output failed
+    2  invalid command line or settings
+    3  the render was stopped by the time limit
+    4  the preview window was closed before the render finished";
+
+pub static MERGE_USAGE: &str = "\
+Usage: rust_gl merge [OPTIONS] <FILM>...
+
+Combines films saved with --film-output by renders with different seeds into one image.
+The filter weighted radiance and the filter weights of every pixel are summed over the films
+before dividing, so each sample counts the same. All films need the same scene, camera and
+settings apart from the seed and the sample counts.
+
+Options:
+    --output <PATH>             Merged image (png, exr or pfm), or a merged film when the extension is .film
+    --exr-precision <TYPE>      Sample type of EXR color channels: half or float
+    --tone-mapper <NAME>        Tone mapping of the preview and PNG output: linear, reinhard,
+                                aces, agx or hable
+    --exposure <EV>             Exposure adjustment in stops before tone mapping
+    --white-point <L>           Radiance that maps to white with the reinhard tone mapper
+    --denoise / --no-denoise    Also save a denoised copy of the merged image
+    -h, --help                  Print this message";
+
+pub struct MergeCommand
+{
+    pub inputs: Vec<String>,
+    pub output: String,
+    pub exr_precision: ExrPrecision,
+    pub display: DisplayTransform,
+    pub denoise: bool,
+    pub help: bool,
+}
+
+#[derive(Default)]
+pub struct CommandLine
+{
+    pub scene: Option<String>,
+    pub headless: bool,
+    pub keep_open: bool,
+    pub fly_speed: Option<f32>,
+    pub help: bool,
+    pub resolution: Option<(u32, u32)>,
+    pub resolution_scale: Option<f32>,
+    pub samples: Option<u32>,
+    pub max_depth: Option<u32>,
+    pub min_depth: Option<u32>,
+    pub max_diffuse_depth: Option<u32>,
+    pub max_specular_depth: Option<u32>,
+    pub threads: Option<u32>,
+    pub tile_size: Option<u32>,
+    pub tile_order: Option<TileOrder>,
+    pub samples_per_pass: Option<u32>,
+    pub adaptive_sampling: Option<bool>,
+    pub adaptive_threshold: Option<f32>,
+    pub adaptive_min_samples: Option<u32>,
+    pub sample_count_output: Option<String>,
+    pub time_limit: Option<f32>,
+    pub noise_threshold: Option<f32>,
+    pub checkpoint: Option<String>,
+    pub checkpoint_interval: Option<f32>,
+    pub resume: Option<String>,
+    pub film_output: Option<String>,
+    pub seed: Option<u64>,
+    pub sampler: Option<SamplerType>,
+    pub output: Option<String>,
+    pub exr_precision: Option<ExrPrecision>,
+    pub tone_mapper: Option<ToneMapper>,
+    pub exposure: Option<f32>,
+    pub white_point: Option<f32>,
+    pub denoise: Option<bool>,
+    pub extended_denoising: Option<bool>,
+    pub gbuffer_samples: Option<u32>,
+    pub aovs: Option<Vec<Aov>>,
+    pub aov_output: Option<String>,
+    pub aov_multilayer: Option<bool>,
+    pub jitter_aa: Option<bool>,
+    pub filter: Option<FilterType>,
+    pub filter_radius: Option<f32>,
+    pub frames: Option<(u32, Option<u32>)>,
+    pub frame_rate: Option<f32>,
+    pub shutter_open: Option<f32>,
+    pub shutter_close: Option<f32>,
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String>
+{
+    let value = value.ok_or(format!("Missing value for '{}'", flag))?;
+    return value.parse::<T>().map_err(|_| format!("Invalid value '{}' for '{}'", value, flag));
+}
+
+fn parse_resolution(flag: &str, value: Option<String>) -> Result<(u32, u32), String>
+{
+    let value = value.ok_or(format!("Missing value for '{}'", flag))?;
+    let error = format!("Invalid value '{}' for '{}', expected WIDTHxHEIGHT", value, flag);
+
+    let mut parts = value.splitn(2, 'x');
+    let width = parts.next().and_then(|w| w.parse::<u32>().ok()).ok_or(error.clone())?;
+    let height = parts.next().and_then(|h| h.parse::<u32>().ok()).ok_or(error.clone())?;
+
+    return Ok((width, height));
+}
+
+fn parse_frames(flag: &str, value: Option<String>) -> Result<(u32, Option<u32>), String>
+{
+    let value = value.ok_or(format!("Missing value for '{}'", flag))?;
+    let error = format!("Invalid value '{}' for '{}', expected N or N..M", value, flag);
+
+    let mut parts = value.splitn(2, "..");
+    let start = parts.next().and_then(|n| n.parse::<u32>().ok()).ok_or(error.clone())?;
+    let end = match parts.next()
+    {
+        Some(m) => Some(m.parse::<u32>().map_err(|_| error.clone())?),
+        None => None,
+    };
+
+    return Ok((start, end));
+}
+
+fn parse_aovs(flag: &str, value: Option<String>) -> Result<Vec<Aov>, String>
+{
+    let value = value.ok_or(format!("Missing value for '{}'", flag))?;
+    return value.split(',').filter(|name| !name.is_empty()).map(|name| name.trim().parse::<Aov>()).collect();
+}
+
+pub fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<CommandLine, String>
+{
+    let mut cmd = CommandLine::default();
+
+    while let Some(arg) = args.next()
+    {
+        match arg.as_str()
+        {
+            "-h" | "--help" => cmd.help = true,
+            "--headless" => cmd.headless = true,
+            "--keep-open" => cmd.keep_open = true,
+            "--fly-speed" => cmd.fly_speed = Some(parse_value(&arg, args.next())?),
+            "--resolution" => cmd.resolution = Some(parse_resolution(&arg, args.next())?),
+            "--resolution-scale" => cmd.resolution_scale = Some(parse_value(&arg, args.next())?),
+            "--spp" => cmd.samples = Some(parse_value(&arg, args.next())?),
+            "--max-depth" => cmd.max_depth = Some(parse_value(&arg, args.next())?),
+            "--min-depth" => cmd.min_depth = Some(parse_value(&arg, args.next())?),
+            "--max-diffuse-depth" => cmd.max_diffuse_depth = Some(parse_value(&arg, args.next())?),
+            "--max-specular-depth" => cmd.max_specular_depth = Some(parse_value(&arg, args.next())?),
+            "--time-limit" => cmd.time_limit = Some(parse_value(&arg, args.next())?),
+            "--noise-threshold" => cmd.noise_threshold = Some(parse_value(&arg, args.next())?),
+            "--checkpoint" => cmd.checkpoint = Some(parse_value(&arg, args.next())?),
+            "--checkpoint-interval" => cmd.checkpoint_interval = Some(parse_value(&arg, args.next())?),
+            "--resume" => cmd.resume = Some(parse_value(&arg, args.next())?),
+            "--film-output" => cmd.film_output = Some(parse_value(&arg, args.next())?),
+            "--threads" => cmd.threads = Some(parse_value(&arg, args.next())?),
+            "--tile-size" => cmd.tile_size = Some(parse_value(&arg, args.next())?),
+            "--tile-order" => cmd.tile_order = Some(parse_value(&arg, args.next())?),
+            "--samples-per-pass" => cmd.samples_per_pass = Some(parse_value(&arg, args.next())?),
+            "--adaptive" => cmd.adaptive_sampling = Some(true),
+            "--no-adaptive" => cmd.adaptive_sampling = Some(false),
+            "--adaptive-threshold" => cmd.adaptive_threshold = Some(parse_value(&arg, args.next())?),
+            "--adaptive-min-samples" => cmd.adaptive_min_samples = Some(parse_value(&arg, args.next())?),
+            "--sample-count-output" => cmd.sample_count_output = Some(parse_value(&arg, args.next())?),
+            "--seed" => cmd.seed = Some(parse_value(&arg, args.next())?),
+            "--sampler" => cmd.sampler = Some(parse_value(&arg, args.next())?),
+            "--output" => cmd.output = Some(parse_value(&arg, args.next())?),
+            "--exr-precision" => cmd.exr_precision = Some(parse_value(&arg, args.next())?),
+            "--tone-mapper" => cmd.tone_mapper = Some(parse_value(&arg, args.next())?),
+            "--exposure" => cmd.exposure = Some(parse_value(&arg, args.next())?),
+            "--white-point" => cmd.white_point = Some(parse_value(&arg, args.next())?),
+            "--denoise" => cmd.denoise = Some(true),
+            "--no-denoise" => cmd.denoise = Some(false),
+            "--extended-denoising" => cmd.extended_denoising = Some(true),
+            "--no-extended-denoising" => cmd.extended_denoising = Some(false),
+            "--gbuffer-samples" => cmd.gbuffer_samples = Some(parse_value(&arg, args.next())?),
+            "--aovs" => cmd.aovs = Some(parse_aovs(&arg, args.next())?),
+            "--aov-output" => cmd.aov_output = Some(parse_value(&arg, args.next())?),
+            "--aov-multilayer" => cmd.aov_multilayer = Some(true),
+            "--no-aov-multilayer" => cmd.aov_multilayer = Some(false),
+            "--jitter-aa" => cmd.jitter_aa = Some(true),
+            "--no-jitter-aa" => cmd.jitter_aa = Some(false),
+            "--filter" => cmd.filter = Some(parse_value(&arg, args.next())?),
+            "--filter-radius" => cmd.filter_radius = Some(parse_value(&arg, args.next())?),
+            "--frames" => cmd.frames = Some(parse_frames(&arg, args.next())?),
+            "--frame-rate" => cmd.frame_rate = Some(parse_value(&arg, args.next())?),
+            "--shutter-open" => cmd.shutter_open = Some(parse_value(&arg, args.next())?),
+            "--shutter-close" => cmd.shutter_close = Some(parse_value(&arg, args.next())?),
+            _ if arg.starts_with('-') => return Err(format!("Unknown option '{}'", arg)),
+            _ if cmd.scene.is_some() => return Err(format!("Unexpected argument '{}'", arg)),
+            _ => cmd.scene = Some(arg),
+        }
+    }
+
+    // The other values are checked with the settings they end up in.
+    if cmd.fly_speed.map_or(false, |speed| !is_positive(speed))
+    {
+        return Err("Fly speed must be positive".to_owned());
+    }
+
+    return Ok(cmd);
+}
+
+pub fn parse_merge_args<I: Iterator<Item = String>>(mut args: I) -> Result<MergeCommand, String>
+{
+    let mut cmd = MergeCommand { inputs: Vec::new(), output: "merged.png".to_owned(), exr_precision: ExrPrecision::Half, display: DisplayTransform::default(), denoise: false, help: false };
+
+    while let Some(arg) = args.next()
+    {
+        match arg.as_str()
+        {
+            "-h" | "--help" => cmd.help = true,
+            "--output" => cmd.output = parse_value(&arg, args.next())?,
+            "--exr-precision" => cmd.exr_precision = parse_value(&arg, args.next())?,
+            "--tone-mapper" => cmd.display.tone_mapper = parse_value(&arg, args.next())?,
+            "--exposure" => cmd.display.exposure = parse_value(&arg, args.next())?,
+            "--white-point" => cmd.display.white_point = parse_value(&arg, args.next())?,
+            "--denoise" => cmd.denoise = true,
+            "--no-denoise" => cmd.denoise = false,
+            _ if arg.starts_with('-') => return Err(format!("Unknown option '{}'", arg)),
+            _ => cmd.inputs.push(arg),
+        }
+    }
+
+    if cmd.help
+    {
+        return Ok(cmd);
+    }
+    if cmd.inputs.is_empty()
+    {
+        return Err("No films to merge".to_owned());
+    }
+
+    let extension = output_extension(&cmd.output);
+    if extension != FILM_EXTENSION && !OUTPUT_FORMATS.contains(&extension.as_str())
+    {
+        return Err(format!("Unsupported output format '{}' (supported: {}, {})", extension, OUTPUT_FORMATS.join(", "), FILM_EXTENSION));
+    }
+    if !cmd.display.exposure.is_finite()
+    {
+        return Err("Exposure must be finite".to_owned());
+    }
+    if !is_positive(cmd.display.white_point)
+    {
+        return Err("White point must be positive".to_owned());
+    }
+    if cmd.denoise && extension == FILM_EXTENSION
+    {
+        return Err("A merged film can't be denoised, merge into an image instead".to_owned());
+    }
+
+    return Ok(cmd);
+}
+
+impl CommandLine
+{
+    pub fn apply(&self, settings: &mut RenderSettings)
+    {
+        if let Some((width, height)) = self.resolution
+        {
+            settings.width = width;
+            settings.height = height;
+        }
+        if let Some(resolution_scale) = self.resolution_scale { settings.resolution_scale = resolution_scale; }
+        if let Some(samples) = self.samples { settings.samples = samples; }
+        if let Some(max_depth) = self.max_depth { settings.max_depth = max_depth; }
+        if let Some(min_depth) = self.min_depth { settings.min_depth = min_depth; }
+        if let Some(max_diffuse_depth) = self.max_diffuse_depth { settings.max_diffuse_depth = max_diffuse_depth; }
+        if let Some(max_specular_depth) = self.max_specular_depth { settings.max_specular_depth = max_specular_depth; }
+        if let Some(time_limit) = self.time_limit { settings.time_limit = time_limit; }
+        if let Some(noise_threshold) = self.noise_threshold { settings.noise_threshold = noise_threshold; }
+        if let Some(checkpoint) = &self.checkpoint { settings.checkpoint = Some(checkpoint.clone()); }
+        if let Some(checkpoint_interval) = self.checkpoint_interval { settings.checkpoint_interval = checkpoint_interval; }
+        // A resumed render keeps checkpointing to the file it came from.
+        if let (None, Some(resume)) = (&settings.checkpoint, &self.resume) { settings.checkpoint = Some(resume.clone()); }
+        if let Some(film_output) = &self.film_output { settings.film_output = Some(film_output.clone()); }
+        if let Some(threads) = self.threads { settings.threads = threads; }
+        if let Some(tile_size) = self.tile_size { settings.tile_size = tile_size; }
+        if let Some(tile_order) = self.tile_order { settings.tile_order = tile_order; }
+        if let Some(samples_per_pass) = self.samples_per_pass { settings.samples_per_pass = samples_per_pass; }
+        if let Some(adaptive_sampling) = self.adaptive_sampling { settings.adaptive_sampling = adaptive_sampling; }
+        if let Some(adaptive_threshold) = self.adaptive_threshold { settings.adaptive_threshold = adaptive_threshold; }
+        if let Some(adaptive_min_samples) = self.adaptive_min_samples { settings.adaptive_min_samples = adaptive_min_samples; }
+        if let Some(sample_count_output) = &self.sample_count_output { settings.sample_count_output = Some(sample_count_output.clone()); }
+        if let Some(seed) = self.seed { settings.seed = seed; }
+        if let Some(sampler) = self.sampler { settings.sampler = sampler; }
+        if let Some(output) = &self.output { settings.output = output.clone(); }
+        if let Some(exr_precision) = self.exr_precision { settings.exr_precision = exr_precision; }
+        if let Some(tone_mapper) = self.tone_mapper { settings.tone_mapper = tone_mapper; }
+        if let Some(exposure) = self.exposure { settings.exposure = exposure; }
+        if let Some(white_point) = self.white_point { settings.white_point = white_point; }
+        if let Some(denoise) = self.denoise { settings.denoise = denoise; }
+        if let Some(extended_denoising) = self.extended_denoising { settings.extended_denoising = extended_denoising; }
+        if let Some(gbuffer_samples) = self.gbuffer_samples { settings.gbuffer_samples = gbuffer_samples; }
+        if let Some(aovs) = &self.aovs { settings.aovs = aovs.clone(); }
+        if let Some(aov_output) = &self.aov_output { settings.aov_output = aov_output.clone(); }
+        if let Some(aov_multilayer) = self.aov_multilayer { settings.aov_multilayer = aov_multilayer; }
+        if let Some(jitter_aa) = self.jitter_aa { settings.jitter_aa = jitter_aa; }
+        if let Some(filter) = self.filter { settings.filter = filter; }
+        if let Some(filter_radius) = self.filter_radius { settings.filter_radius = Some(filter_radius); }
+        if let Some((frame_start, frame_end)) = self.frames
+        {
+            settings.frame_start = frame_start;
+            settings.frame_end = frame_end;
+        }
+        if let Some(frame_rate) = self.frame_rate { settings.frame_rate = frame_rate; }
+        if let Some(shutter_open) = self.shutter_open { settings.shutter_open = shutter_open; }
+        if let Some(shutter_close) = self.shutter_close { settings.shutter_close = shutter_close; }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn parse_frames_takes_a_frame_or_a_range()
+    {
+        assert_eq!(parse_frames("--frames", Some("5".to_owned())), Ok((5, None)));
+        assert_eq!(parse_frames("--frames", Some("2..10".to_owned())), Ok((2, Some(10))));
+    }
+
+    #[test]
+    fn parse_frames_rejects_malformed_values()
+    {
+        for value in &["", "x", "3..", "..4", "1..2..3", "-1"]
+        {
+            assert!(parse_frames("--frames", Some(value.to_string())).is_err(), "accepted '{}'", value);
+        }
+        assert!(parse_frames("--frames", None).is_err());
+    }
+
+    #[test]
+    fn rejects_nan_values_outside_the_settings()
+    {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>().into_iter();
+
+        assert!(parse_args(args(&["--fly-speed", "nan"])).is_err());
+        assert!(parse_args(args(&["--fly-speed", "2.5"])).is_ok());
+        assert!(parse_merge_args(args(&["--exposure", "nan", "a.film"])).is_err());
+        assert!(parse_merge_args(args(&["--white-point", "nan", "a.film"])).is_err());
+    }
+}
@@ -0,0 +1,70 @@
+use serde::Deserialize;
+
+// How the stored values of an image relate to linear light.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorSpace
+{
+    // sRGB transfer function, used by color textures and LDR output.
+    Srgb,
+    // Values are used as they are, for HDR images and data like roughness or normal maps.
+    Linear,
+}
+
+// sRGB transfer functions from IEC 61966-2-1, with the linear segment near black.
+pub fn srgb_to_linear(value: f32) -> f32
+{
+    if value <= 0.04045f32
+    {
+        return value / 12.92f32;
+    }
+
+    return ((value + 0.055f32) / 1.055f32).powf(2.4f32);
+}
+
+pub fn linear_to_srgb(value: f32) -> f32
+{
+    if value <= 0.0031308f32
+    {
+        return value * 12.92f32;
+    }
+
+    return 1.055f32 * value.powf(1f32 / 2.4f32) - 0.055f32;
+}
+
+impl ColorSpace
+{
+    pub fn to_linear(&self, value: f32) -> f32
+    {
+        return match self
+        {
+            ColorSpace::Srgb => srgb_to_linear(value),
+            ColorSpace::Linear => value,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn srgb_known_values()
+    {
+        assert_eq!(srgb_to_linear(0f32), 0f32);
+        assert!((srgb_to_linear(1f32) - 1f32).abs() < 1e-6);
+        assert!((srgb_to_linear(0.5f32) - 0.214041).abs() < 1e-5);
+        assert!((linear_to_srgb(0.18f32) - 0.461356).abs() < 1e-5);
+    }
+
+    #[test]
+    fn srgb_round_trip()
+    {
+        for i in 0..=100
+        {
+            let value = i as f32 / 100f32;
+            assert!((linear_to_srgb(srgb_to_linear(value)) - value).abs() < 1e-5, "{}", value);
+        }
+    }
+}
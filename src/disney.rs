@@ -1,12 +1,15 @@
 use crate::scene::*;
-use rand::rngs::SmallRng as RandGenerator;
 use crate::material::*;
-use std::f32::consts::{ FRAC_PI_2, PI};
-use crate::random::{next_rand, next_rand_v2};
+use std::f32::consts::PI;
+use crate::light::power_heuristic;
+use crate::sampler::Sampler;
 
 static TWO_PI: f32 = 6.283185307179586476925286766559;
 
 pub static MIN_N_DOT_V: f32 = 1e-4;
+pub static EPSILON: f32 = 0.0001f32;
+// Shadow rays stop just short of the light so they don't hit the light itself.
+static SHADOW_EPSILON: f32 = 0.001f32;
 
 pub fn reflect(i: glm::Vec3, n: glm::Vec3) -> glm::Vec3
 {
@@ -62,7 +65,7 @@ fn shadow_terminator_term_chiang2019(hit: &Hit, l: glm::Vec3) -> f32
 fn cos_hemisphere_sample(u: glm::Vec2) -> glm::Vec3
 {
     let r = u.x.sqrt();
-    let phi = FRAC_PI_2 * u.y;
+    let phi = TWO_PI * u.y;
     let mut dir = glm::vec3(
         r * phi.cos(),
         r * phi.sin(),
@@ -78,7 +81,7 @@ fn spherical_direction(sin_theta: f32, cos_theta: f32, sin_phi: f32, cos_phi: f3
 }
 
 // Aproximate Luminance
-fn luminance(rgb: glm::Vec3) -> f32
+pub fn luminance(rgb: glm::Vec3) -> f32
 {
     return glm::dot(rgb, glm::vec3(0.2126f32, 0.7152f32, 0.0722f32));
 }
@@ -172,15 +175,12 @@ fn pdf_diffuse(hit: &Hit, l: glm::Vec3) -> f32
 
 fn sample_diffuse(hit: &Hit, v: glm::Vec3, u: glm::Vec2) -> glm::Vec3
 {
-    let mut h = cos_hemisphere_sample(u);
-    h = (hit.tangent * h.x) + (hit.bitangent * h.y) + (hit.normal * h.z);
+    let h = cos_hemisphere_sample(u);
 
-    if glm::dot(v, hit.normal) < 0f32 // TODO: Duplicate dot product
-    {
-        h.z *= -1f32;
-    }
+    // Sample the hemisphere on the side of v.
+    let n = if glm::dot(v, hit.normal) < 0f32 { -hit.normal } else { hit.normal }; // TODO: Duplicate dot product
 
-    return h;
+    return (hit.tangent * h.x) + (hit.bitangent * h.y) + (n * h.z);
 }
 
 // Microfacet Isotropic
@@ -237,18 +237,25 @@ pub fn pdf(hit: &Hit, material: &SurfaceMaterial, v: glm::Vec3, l: glm::Vec3) ->
     return (pdf_diff + pdf_spec) / 2f32;
 }
 
-pub fn sample(hit: &Hit, material: &SurfaceMaterial, v: glm::Vec3, rng: &mut RandGenerator) -> glm::Vec3
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Lobe
+{
+    Diffuse,
+    Specular,
+}
+
+pub fn sample(hit: &Hit, material: &SurfaceMaterial, v: glm::Vec3, sampler: &mut dyn Sampler) -> (glm::Vec3, Lobe)
 {
-    let u = next_rand_v2(rng);
-    let rnd = next_rand(rng);
+    let rnd = sampler.next_1d();
+    let u = sampler.next_2d();
 
     if rnd <= 0.5f32
     {
-        return sample_diffuse(&hit, v, u);
+        return (sample_diffuse(&hit, v, u), Lobe::Diffuse);
     }
     else
     {
-        return sample_specular_isotropic(&hit, &material, v, u);
+        return (sample_specular_isotropic(&hit, &material, v, u), Lobe::Specular);
     }
 }
 
@@ -265,16 +272,32 @@ pub fn evaluate(material: &SurfaceMaterial, n_dot_l: f32, n_dot_v: f32, n_dot_h:
     return retval * n_dot_l;
 }
 
-pub fn direct_lighting(scene: &SceneGraph, hit: &Hit, material: &SurfaceMaterial, v: glm::Vec3) -> glm::Vec3
+// Next event estimation: samples a point on a light and weights it against BSDF sampling with the power heuristic.
+// Without `mis` the path ends at this hit, so only the light sample can reach the light.
+pub fn direct_lighting(scene: &SceneGraph, hit: &Hit, material: &SurfaceMaterial, v: glm::Vec3, mis: bool, sampler: &mut dyn Sampler) -> glm::Vec3
 {
-    //let l = glm::normalize(glm::vec3(0.5f32, 1f32, -0.9f32));
-    let l = glm::normalize(glm::vec3(0f32, 1f32, 0f32));
-    let h = glm::normalize(v + l);
+    let u_select = sampler.next_1d();
+    let u = sampler.next_2d();
 
-    let emission = /*light color*/ glm::vec3(1f32, 1f32, 1f32) * 2f32;
+    let light = match scene.sample_light(hit.pos, u_select, u, hit.time)
+    {
+        Some(light) => light,
+        None => return glm::vec3(0f32, 0f32, 0f32),
+    };
 
-    let obstructed = scene.traverse_shadow(hit.pos, l, 1000f32);
-    let shadow_mul = !obstructed as i32 as f32;
+    let l = light.direction;
+    if !same_hemisphere(&hit, v, l)
+    {
+        return glm::vec3(0f32, 0f32, 0f32);
+    }
+
+    let shadow_origin = hit.pos + (l * EPSILON);
+    if scene.traverse_shadow(shadow_origin, l, light.distance * (1f32 - SHADOW_EPSILON), hit.time)
+    {
+        return glm::vec3(0f32, 0f32, 0f32);
+    }
+
+    let h = glm::normalize(v + l);
 
     let n_dot_l = glm::dot(hit.normal, l).abs();
     let n_dot_v = glm::dot(hit.normal, v).abs().max(MIN_N_DOT_V); // TODO: minimum ndotv?
@@ -282,8 +305,8 @@ pub fn direct_lighting(scene: &SceneGraph, hit: &Hit, material: &SurfaceMaterial
     let l_dot_h = glm::dot(l, h).abs();
 
     //let shadow_term = shadow_terminator_term_chiang2019(&hit, l);
-    let f = evaluate(&material, n_dot_l, n_dot_v, n_dot_h, l_dot_h) * /*shadow_term **/ shadow_mul;
-    let lighting = f * emission;
+    let f = evaluate(&material, n_dot_l, n_dot_v, n_dot_h, l_dot_h) /* * shadow_term*/;
+    let weight = if mis { power_heuristic(light.pdf, pdf(&hit, &material, v, l)) } else { 1f32 };
 
-    return lighting;
+    return f * light.emission * (weight / light.pdf);
 }
@@ -0,0 +1,172 @@
+// Piecewise constant 1D distribution used to importance sample lights.
+pub struct Distribution1D
+{
+    pub func: Vec<f32>,
+    pub cdf: Vec<f32>,
+    pub func_int: f32,
+}
+
+// Marginal/conditional distribution over a 2D function, e.g. the texels of an environment map.
+pub struct Distribution2D
+{
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution1D
+{
+    pub fn new(func: &[f32]) -> Distribution1D
+    {
+        let n = func.len();
+        let func: Vec<f32> = func.iter().map(|f| f.max(0f32)).collect();
+        let mut cdf = vec![0f32; n + 1];
+        for i in 0..n
+        {
+            cdf[i + 1] = cdf[i] + func[i] / n as f32;
+        }
+
+        let func_int = cdf[n];
+        for i in 1..(n + 1)
+        {
+            cdf[i] = if func_int > 0f32 { cdf[i] / func_int } else { i as f32 / n as f32 };
+        }
+
+        Distribution1D
+        {
+            func,
+            cdf,
+            func_int,
+        }
+    }
+
+    pub fn count(&self) -> usize
+    {
+        return self.func.len();
+    }
+
+    // Index of the segment that contains u.
+    fn find_segment(&self, u: f32) -> usize
+    {
+        let idx = self.cdf.partition_point(|&c| c <= u);
+        return idx.max(1).min(self.count()) - 1;
+    }
+
+    pub fn pmf(&self, index: usize) -> f32
+    {
+        return self.cdf[index + 1] - self.cdf[index];
+    }
+
+    // Returns the sampled index and its probability.
+    pub fn sample_discrete(&self, u: f32) -> (usize, f32)
+    {
+        let index = self.find_segment(u);
+        return (index, self.pmf(index));
+    }
+
+    // Returns a value in [0, 1), its density and the segment it falls in.
+    pub fn sample_continuous(&self, u: f32) -> (f32, f32, usize)
+    {
+        let offset = self.find_segment(u);
+
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0f32
+        {
+            du /= width;
+        }
+
+        let pdf = if self.func_int > 0f32 { self.func[offset] / self.func_int } else { 1f32 };
+        return ((offset as f32 + du) / self.count() as f32, pdf, offset);
+    }
+}
+
+impl Distribution2D
+{
+    // `func` is stored row by row, `nu` values per row.
+    pub fn new(func: &[f32], nu: usize, nv: usize) -> Distribution2D
+    {
+        let conditional: Vec<Distribution1D> = (0..nv).map(|v| Distribution1D::new(&func[v * nu..(v + 1) * nu])).collect();
+        let marginal_func: Vec<f32> = conditional.iter().map(|c| c.func_int).collect();
+
+        Distribution2D
+        {
+            conditional,
+            marginal: Distribution1D::new(&marginal_func),
+        }
+    }
+
+    // Returns a point in [0, 1)^2 and its density.
+    pub fn sample(&self, u: glm::Vec2) -> (glm::Vec2, f32)
+    {
+        let (d1, pdf1, v) = self.marginal.sample_continuous(u.y);
+        let (d0, pdf0, _) = self.conditional[v].sample_continuous(u.x);
+
+        return (glm::vec2(d0, d1), pdf0 * pdf1);
+    }
+
+    pub fn pdf(&self, p: glm::Vec2) -> f32
+    {
+        let nu = self.conditional[0].count();
+        let nv = self.marginal.count();
+        let iu = ((p.x * nu as f32) as usize).min(nu - 1);
+        let iv = ((p.y * nv as f32) as usize).min(nv - 1);
+
+        if self.marginal.func_int <= 0f32
+        {
+            return 0f32;
+        }
+
+        return self.conditional[iv].func[iu] / self.marginal.func_int;
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn discrete_samples_follow_the_function()
+    {
+        let distribution = Distribution1D::new(&[1f32, 3f32, 0f32, 4f32]);
+
+        assert_eq!(distribution.func_int, 2f32);
+        assert_eq!(distribution.pmf(1), 0.375f32);
+        assert_eq!(distribution.sample_discrete(0.1f32), (0, 0.125f32));
+        assert_eq!(distribution.sample_discrete(0.2f32), (1, 0.375f32));
+        // The empty segment is never picked.
+        assert_eq!(distribution.sample_discrete(0.5f32).0, 3);
+    }
+
+    #[test]
+    fn continuous_samples_have_the_function_as_density()
+    {
+        let distribution = Distribution1D::new(&[1f32, 3f32, 0f32, 4f32]);
+
+        let (x, pdf, segment) = distribution.sample_continuous(0.3125f32);
+        assert_eq!((x, pdf, segment), (0.375f32, 1.5f32, 1));
+
+        let (x, pdf, segment) = distribution.sample_continuous(0.75f32);
+        assert_eq!((x, pdf, segment), (0.875f32, 2f32, 3));
+    }
+
+    #[test]
+    fn zero_function_samples_uniformly()
+    {
+        let distribution = Distribution1D::new(&[0f32, 0f32]);
+
+        assert_eq!(distribution.sample_continuous(0.75f32), (0.75f32, 1f32, 1));
+    }
+
+    #[test]
+    fn pdf_matches_the_sampled_density_in_2d()
+    {
+        let distribution = Distribution2D::new(&[1f32, 2f32, 3f32, 0f32, 5f32, 1f32], 3, 2);
+
+        for &(u, v) in &[(0.1f32, 0.2f32), (0.5f32, 0.5f32), (0.9f32, 0.7f32)]
+        {
+            let (p, pdf) = distribution.sample(glm::vec2(u, v));
+            assert!((distribution.pdf(p) - pdf).abs() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,192 @@
+extern crate exr;
+
+use std::f32::consts::PI;
+use std::io::BufReader;
+use crate::distribution::Distribution2D;
+use crate::disney::luminance;
+use crate::light::LightSample;
+
+struct EnvironmentMap
+{
+    width: usize,
+    height: usize,
+    // Rows from top (+Y) to bottom (-Y).
+    pixels: Vec<glm::Vec3>,
+    distribution: Distribution2D,
+}
+
+// Light arriving from infinitely far away: a constant color or an equirectangular HDR image.
+pub struct Environment
+{
+    pub color: glm::Vec3,
+    pub intensity: f32,
+    // Rotation around the Y axis in radians.
+    pub rotation: f32,
+    map: Option<EnvironmentMap>,
+}
+
+fn load_hdr(path: &str) -> Result<(usize, usize, Vec<glm::Vec3>), String>
+{
+    let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let decoder = image::codecs::hdr::HdrDecoder::new(BufReader::new(file)).map_err(|e| format!("{}: {}", path, e))?;
+    let meta = decoder.metadata();
+    let pixels = decoder.read_image_hdr().map_err(|e| format!("{}: {}", path, e))?;
+
+    return Ok((meta.width as usize, meta.height as usize, pixels.iter().map(|p| glm::vec3(p[0], p[1], p[2])).collect()));
+}
+
+fn load_exr(path: &str) -> Result<(usize, usize, Vec<glm::Vec3>), String>
+{
+    let image = exr::prelude::read_first_rgba_layer_from_file(
+        path,
+        |resolution, _| (resolution.width(), vec![glm::vec3(0f32, 0f32, 0f32); resolution.width() * resolution.height()]),
+        |(width, pixels), position, (r, g, b, _a): (f32, f32, f32, f32)| pixels[position.y() * *width + position.x()] = glm::vec3(r, g, b),
+    ).map_err(|e| format!("{}: {}", path, e))?;
+
+    let size = image.layer_data.size;
+    let (_, pixels) = image.layer_data.channel_data.pixels;
+
+    return Ok((size.width(), size.height(), pixels));
+}
+
+// Maps a direction to equirectangular coordinates, both in [0, 1].
+fn direction_to_uv(direction: glm::Vec3, rotation: f32) -> glm::Vec2
+{
+    let theta = direction.y.max(-1f32).min(1f32).acos();
+    let mut phi = direction.z.atan2(direction.x) - rotation;
+    phi = phi.rem_euclid(2f32 * PI);
+
+    return glm::vec2(phi / (2f32 * PI), theta / PI);
+}
+
+fn uv_to_direction(uv: glm::Vec2, rotation: f32) -> glm::Vec3
+{
+    let theta = uv.y * PI;
+    let phi = uv.x * 2f32 * PI + rotation;
+    let sin_theta = theta.sin();
+
+    return glm::vec3(sin_theta * phi.cos(), theta.cos(), sin_theta * phi.sin());
+}
+
+impl EnvironmentMap
+{
+    fn new(width: usize, height: usize, pixels: Vec<glm::Vec3>) -> EnvironmentMap
+    {
+        // Weight by sin(theta) so the poles, which are stretched out in the image, are not oversampled.
+        let mut func = vec![0f32; width * height];
+        for y in 0..height
+        {
+            let sin_theta = (PI * (y as f32 + 0.5f32) / height as f32).sin();
+            for x in 0..width
+            {
+                func[y * width + x] = luminance(pixels[y * width + x]) * sin_theta;
+            }
+        }
+
+        EnvironmentMap
+        {
+            width,
+            height,
+            distribution: Distribution2D::new(&func, width, height),
+            pixels,
+        }
+    }
+
+    fn lookup(&self, uv: glm::Vec2) -> glm::Vec3
+    {
+        let x = ((uv.x * self.width as f32) as usize).min(self.width - 1);
+        let y = ((uv.y * self.height as f32) as usize).min(self.height - 1);
+
+        return self.pixels[y * self.width + x];
+    }
+}
+
+impl Environment
+{
+    pub fn constant(color: glm::Vec3) -> Environment
+    {
+        Environment
+        {
+            color,
+            intensity: 1f32,
+            rotation: 0f32,
+            map: None,
+        }
+    }
+
+    // Loads a Radiance (.hdr) or OpenEXR (.exr) equirectangular image.
+    pub fn load_image(&mut self, path: &str) -> Result<(), String>
+    {
+        let (width, height, pixels) = match crate::settings::output_extension(path).as_str()
+        {
+            "hdr" => load_hdr(path)?,
+            "exr" => load_exr(path)?,
+            extension => return Err(format!("{}: unsupported environment format '{}'", path, extension)),
+        };
+
+        if width == 0 || height == 0
+        {
+            return Err(format!("{}: empty image", path));
+        }
+
+        self.map = Some(EnvironmentMap::new(width, height, pixels));
+        return Ok(());
+    }
+
+    // Only image based environments are sampled as a light, a constant one is found well enough by BSDF sampling.
+    pub fn is_importance_sampled(&self) -> bool
+    {
+        return self.map.is_some() && self.intensity > 0f32;
+    }
+
+    pub fn eval(&self, direction: glm::Vec3) -> glm::Vec3
+    {
+        return match &self.map
+        {
+            Some(map) => map.lookup(direction_to_uv(direction, self.rotation)) * self.intensity,
+            None => self.color * self.intensity,
+        };
+    }
+
+    pub fn sample(&self, u: glm::Vec2) -> Option<LightSample>
+    {
+        let map = self.map.as_ref()?;
+        let (uv, map_pdf) = map.distribution.sample(u);
+        if map_pdf <= 0f32
+        {
+            return None;
+        }
+
+        let sin_theta = (uv.y * PI).sin();
+        if sin_theta <= 0f32
+        {
+            return None;
+        }
+
+        Some(LightSample
+        {
+            direction: uv_to_direction(uv, self.rotation),
+            distance: std::f32::MAX,
+            emission: map.lookup(uv) * self.intensity,
+            pdf: map_pdf / (2f32 * PI * PI * sin_theta),
+        })
+    }
+
+    // Solid angle pdf of `sample` returning `direction`.
+    pub fn pdf(&self, direction: glm::Vec3) -> f32
+    {
+        if let Some(map) = &self.map
+        {
+            let uv = direction_to_uv(direction, self.rotation);
+            let sin_theta = (uv.y * PI).sin();
+            if sin_theta <= 0f32
+            {
+                return 0f32;
+            }
+
+            return map.distribution.pdf(uv) / (2f32 * PI * PI * sin_theta);
+        }
+
+        return 0f32;
+    }
+}
@@ -0,0 +1,329 @@
+use crate::application::{Pixel, PixelStats};
+use crate::binary::Reader;
+use crate::filter::Filter;
+use crate::tile::Tile;
+
+// Layout, all values little endian:
+//   magic "RGLFILM\0", version u32, fingerprint u64, width u32, height u32, seed count u32, seeds u64,
+//   then per pixel: weighted sum r, g, b f64, weight f64, sample count u32.
+static MAGIC: &[u8; 8] = b"RGLFILM\0";
+static VERSION: u32 = 2;
+static PIXEL_SIZE: usize = 36;
+
+// Filter weighted sum of the samples splatted into a pixel.
+#[derive(Copy, Clone, Default)]
+pub struct FilmPixel
+{
+    pub sum: [f64; 3],
+    pub weight: f64,
+}
+
+impl FilmPixel
+{
+    pub fn add(&mut self, color: glm::Vec3, weight: f32)
+    {
+        let weight = weight as f64;
+        self.sum[0] += color.x as f64 * weight;
+        self.sum[1] += color.y as f64 * weight;
+        self.sum[2] += color.z as f64 * weight;
+        self.weight += weight;
+    }
+
+    pub fn merge(&mut self, other: &FilmPixel)
+    {
+        for c in 0..3
+        {
+            self.sum[c] += other.sum[c];
+        }
+        self.weight += other.weight;
+    }
+
+    // Weighted mean. Negative filter lobes can push the weight or color below zero where there are few samples.
+    pub fn resolve(&self) -> Pixel
+    {
+        if self.weight <= 0f64
+        {
+            return Pixel { r: 0f32, g: 0f32, b: 0f32, a: 1f32 };
+        }
+
+        let resolve = |sum: f64| (sum / self.weight).max(0f64) as f32;
+        return Pixel { r: resolve(self.sum[0]), g: resolve(self.sum[1]), b: resolve(self.sum[2]), a: 1f32 };
+    }
+}
+
+// Film of a tile grown by the filter padding (clipped to the image), so samples near the tile edge can splat
+// into pixels of the neighbouring tiles. Added to the full film in tile order after the pass.
+pub struct SplatTile
+{
+    x0: u32,
+    y0: u32,
+    x1: u32,
+    y1: u32,
+    pixels: Vec<FilmPixel>,
+}
+
+impl SplatTile
+{
+    pub fn new(tile: &Tile, padding: u32, width: u32, height: u32) -> SplatTile
+    {
+        let x0 = tile.x0.saturating_sub(padding);
+        let y0 = tile.y0.saturating_sub(padding);
+        let x1 = (tile.x1 + padding).min(width);
+        let y1 = (tile.y1 + padding).min(height);
+
+        SplatTile { x0, y0, x1, y1, pixels: vec![FilmPixel::default(); ((x1 - x0) * (y1 - y0)) as usize] }
+    }
+
+    // `pos` is the film position of the sample in pixels, with pixel centers at integer coordinates.
+    pub fn splat(&mut self, pos: glm::Vec2, color: glm::Vec3, filter: &Filter)
+    {
+        // Pixels in (pos - radius, pos + radius], half open so a box of radius 0.5 covers exactly one pixel.
+        let x_min = ((pos.x - filter.radius).floor() as i64 + 1).max(self.x0 as i64);
+        let x_max = ((pos.x + filter.radius).floor() as i64).min(self.x1 as i64 - 1);
+        let y_min = ((pos.y - filter.radius).floor() as i64 + 1).max(self.y0 as i64);
+        let y_max = ((pos.y + filter.radius).floor() as i64).min(self.y1 as i64 - 1);
+
+        let stride = (self.x1 - self.x0) as i64;
+        for y in y_min..=y_max
+        {
+            for x in x_min..=x_max
+            {
+                let weight = filter.eval(glm::vec2(pos.x - x as f32, pos.y - y as f32));
+                if weight != 0f32
+                {
+                    self.pixels[((y - self.y0 as i64) * stride + (x - self.x0 as i64)) as usize].add(color, weight);
+                }
+            }
+        }
+    }
+
+    pub fn add_to(&self, film: &mut [FilmPixel], width: u32)
+    {
+        let stride = (self.x1 - self.x0) as usize;
+        for (row, y) in (self.y0..self.y1).enumerate()
+        {
+            let start = (y * width + self.x0) as usize;
+            for (pixel, splat) in film[start..start + stride].iter_mut().zip(&self.pixels[row * stride..(row + 1) * stride])
+            {
+                pixel.merge(splat);
+            }
+        }
+    }
+}
+
+// Unnormalized film: sums of weighted samples that can be added up across independent renders.
+pub struct RawFilm
+{
+    pub width: u32,
+    pub height: u32,
+    // `RenderSettings::film_fingerprint` of the renders, only films of the same scene, camera and settings merge.
+    pub fingerprint: u64,
+    // Seeds of the renders in this film, renders with the same seed would count the same samples twice.
+    pub seeds: Vec<u64>,
+    pub sums: Vec<[f64; 3]>,
+    pub weights: Vec<f64>,
+    pub counts: Vec<u32>,
+}
+
+impl RawFilm
+{
+    pub fn from_film(width: u32, height: u32, fingerprint: u64, seed: u64, film: &[FilmPixel], stats: &[PixelStats]) -> RawFilm
+    {
+        RawFilm
+        {
+            width,
+            height,
+            fingerprint,
+            seeds: vec![seed],
+            sums: film.iter().map(|pixel| pixel.sum).collect(),
+            weights: film.iter().map(|pixel| pixel.weight).collect(),
+            counts: stats.iter().map(|s| s.count).collect(),
+        }
+    }
+
+    pub fn merge(&mut self, other: &RawFilm) -> Result<(), String>
+    {
+        if self.width != other.width || self.height != other.height
+        {
+            return Err(format!("Can't merge a {}x{} film into a {}x{} one", other.width, other.height, self.width, self.height));
+        }
+        if self.fingerprint != other.fingerprint
+        {
+            return Err("Rendered with a different scene, camera or settings than the other films".to_owned());
+        }
+        if let Some(seed) = other.seeds.iter().find(|s| self.seeds.contains(s))
+        {
+            return Err(format!("Both films contain a render with seed {}, its samples would be counted twice", seed));
+        }
+
+        self.seeds.extend_from_slice(&other.seeds);
+        for i in 0..self.sums.len()
+        {
+            for c in 0..3
+            {
+                self.sums[i][c] += other.sums[i][c];
+            }
+            self.weights[i] += other.weights[i];
+            self.counts[i] += other.counts[i];
+        }
+
+        return Ok(());
+    }
+
+    // Weighted mean per pixel.
+    pub fn resolve(&self) -> Vec<Pixel>
+    {
+        return self.sums.iter().zip(&self.weights).map(|(&sum, &weight)| FilmPixel { sum, weight }.resolve()).collect();
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String>
+    {
+        let mut data = Vec::with_capacity(40 + self.seeds.len() * 8 + self.sums.len() * PIXEL_SIZE);
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        data.extend_from_slice(&self.fingerprint.to_le_bytes());
+        data.extend_from_slice(&self.width.to_le_bytes());
+        data.extend_from_slice(&self.height.to_le_bytes());
+        data.extend_from_slice(&(self.seeds.len() as u32).to_le_bytes());
+        for seed in &self.seeds
+        {
+            data.extend_from_slice(&seed.to_le_bytes());
+        }
+
+        for i in 0..self.sums.len()
+        {
+            for value in &[self.sums[i][0], self.sums[i][1], self.sums[i][2], self.weights[i]]
+            {
+                data.extend_from_slice(&value.to_bits().to_le_bytes());
+            }
+            data.extend_from_slice(&self.counts[i].to_le_bytes());
+        }
+
+        std::fs::write(path, &data).map_err(|e| format!("Failed to write film '{}': {}", path, e))?;
+
+        println!("Saved Film: {}", path);
+        return Ok(());
+    }
+
+    pub fn load(path: &str) -> Result<RawFilm, String>
+    {
+        let data = std::fs::read(path).map_err(|e| format!("Failed to read film '{}': {}", path, e))?;
+        let mut reader = Reader::new(&data);
+        let error = |e: String| format!("Invalid film '{}': {}", path, e);
+
+        if reader.bytes(MAGIC.len()).map_err(error)? != MAGIC
+        {
+            return Err(format!("'{}' is not a film", path));
+        }
+
+        let version = reader.u32().map_err(error)?;
+        if version != VERSION
+        {
+            return Err(format!("Film '{}' has version {}, expected {}", path, version, VERSION));
+        }
+
+        let fingerprint = reader.u64().map_err(error)?;
+        let width = reader.u32().map_err(error)?;
+        let height = reader.u32().map_err(error)?;
+        let seed_count = reader.u32().map_err(error)?;
+        let seed_count = reader.records(seed_count as u64, 8).map_err(error)?;
+        let mut seeds = Vec::with_capacity(seed_count);
+        for _ in 0..seed_count
+        {
+            seeds.push(reader.u64().map_err(error)?);
+        }
+
+        let count = reader.pixels(width, height, PIXEL_SIZE).map_err(error)?;
+        let mut film = RawFilm { width, height, fingerprint, seeds, sums: Vec::with_capacity(count), weights: Vec::with_capacity(count), counts: Vec::with_capacity(count) };
+        for _ in 0..count
+        {
+            film.sums.push([reader.f64().map_err(error)?, reader.f64().map_err(error)?, reader.f64().map_err(error)?]);
+            film.weights.push(reader.f64().map_err(error)?);
+            film.counts.push(reader.u32().map_err(error)?);
+        }
+
+        return Ok(film);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::test_util::temp_path;
+
+    fn test_film(seed: u64) -> RawFilm
+    {
+        RawFilm
+        {
+            width: 2,
+            height: 2,
+            fingerprint: 0x1234,
+            seeds: vec![seed],
+            sums: (0..4).map(|i| [i as f64, 2f64 * i as f64, 0.5]).collect(),
+            weights: vec![1f64, 2f64, 0f64, 4f64],
+            counts: vec![1, 2, 0, 4],
+        }
+    }
+
+    #[test]
+    fn save_and_load_round_trip()
+    {
+        let path = temp_path("round_trip.film");
+        let film = test_film(3);
+        film.save(&path).unwrap();
+
+        let loaded = RawFilm::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((loaded.width, loaded.height, loaded.fingerprint), (2, 2, 0x1234));
+        assert_eq!(loaded.seeds, film.seeds);
+        assert_eq!(loaded.sums, film.sums);
+        assert_eq!(loaded.weights, film.weights);
+        assert_eq!(loaded.counts, film.counts);
+    }
+
+    #[test]
+    fn rejects_corrupt_files()
+    {
+        let path = temp_path("corrupt.film");
+        test_film(3).save(&path).unwrap();
+        let data = std::fs::read(&path).unwrap();
+
+        std::fs::write(&path, &data[..data.len() - 1]).unwrap();
+        let truncated = RawFilm::load(&path);
+
+        // Seed count after the magic, version, fingerprint, width and height.
+        let mut huge_seed_count = data.clone();
+        huge_seed_count[28..32].copy_from_slice(&[0xff; 4]);
+        std::fs::write(&path, &huge_seed_count).unwrap();
+        let oversized = RawFilm::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(truncated.is_err());
+        assert!(oversized.is_err());
+    }
+
+    #[test]
+    fn merge_adds_samples_and_rejects_a_seed_twice()
+    {
+        let mut film = test_film(1);
+        film.merge(&test_film(2)).unwrap();
+
+        assert_eq!(film.seeds, vec![1, 2]);
+        assert_eq!(film.weights, vec![2f64, 4f64, 0f64, 8f64]);
+        assert_eq!(film.counts, vec![2, 4, 0, 8]);
+        assert!(film.merge(&test_film(2)).is_err());
+    }
+
+    #[test]
+    fn merge_rejects_another_fingerprint()
+    {
+        let mut film = test_film(1);
+        let mut other = test_film(2);
+        other.fingerprint = 0x4321;
+
+        assert!(film.merge(&other).is_err());
+        assert_eq!(film.seeds, vec![1]);
+    }
+}
@@ -0,0 +1,117 @@
+use serde::Deserialize;
+use std::f32::consts::PI;
+
+// Pixel reconstruction filters, all separable.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterType
+{
+    Box,
+    Tent,
+    Gaussian,
+    // Mitchell-Netravali with B = C = 1/3, has small negative lobes.
+    Mitchell,
+    BlackmanHarris,
+}
+
+impl std::str::FromStr for FilterType
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s
+        {
+            "box" => Ok(FilterType::Box),
+            "tent" => Ok(FilterType::Tent),
+            "gaussian" => Ok(FilterType::Gaussian),
+            "mitchell" => Ok(FilterType::Mitchell),
+            "blackman_harris" => Ok(FilterType::BlackmanHarris),
+            _ => Err(format!("Unknown filter '{}'", s)),
+        }
+    }
+}
+
+impl FilterType
+{
+    // Radius in pixels used when none is configured.
+    pub fn default_radius(&self) -> f32
+    {
+        return match self
+        {
+            FilterType::Box => 0.5f32,
+            FilterType::Tent => 1f32,
+            FilterType::Gaussian => 1.5f32,
+            FilterType::Mitchell => 2f32,
+            FilterType::BlackmanHarris => 2f32,
+        };
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Filter
+{
+    pub filter_type: FilterType,
+    pub radius: f32,
+}
+
+fn mitchell(x: f32) -> f32
+{
+    let (b, c) = (1f32 / 3f32, 1f32 / 3f32);
+    let x = x.abs();
+
+    if x < 1f32
+    {
+        return ((12f32 - 9f32 * b - 6f32 * c) * x * x * x + (-18f32 + 12f32 * b + 6f32 * c) * x * x + (6f32 - 2f32 * b)) / 6f32;
+    }
+    if x < 2f32
+    {
+        return ((-b - 6f32 * c) * x * x * x + (6f32 * b + 30f32 * c) * x * x + (-12f32 * b - 48f32 * c) * x + (8f32 * b + 24f32 * c)) / 6f32;
+    }
+
+    return 0f32;
+}
+
+impl Filter
+{
+    fn eval_1d(&self, x: f32) -> f32
+    {
+        let r = self.radius;
+        if x.abs() > r
+        {
+            return 0f32;
+        }
+
+        return match self.filter_type
+        {
+            FilterType::Box => 1f32,
+            FilterType::Tent => 1f32 - x.abs() / r,
+            FilterType::Gaussian =>
+            {
+                // Shifted down so it reaches zero at the radius instead of being cut off.
+                let sigma = r / 3f32;
+                let gaussian = |x: f32| (-x * x / (2f32 * sigma * sigma)).exp();
+                gaussian(x) - gaussian(r)
+            },
+            // The cubic spans two units, stretched over the radius.
+            FilterType::Mitchell => mitchell(2f32 * x / r),
+            FilterType::BlackmanHarris =>
+            {
+                let n = (x + r) / (2f32 * r);
+                0.35875f32 - 0.48829f32 * (2f32 * PI * n).cos() + 0.14128f32 * (4f32 * PI * n).cos() - 0.01168f32 * (6f32 * PI * n).cos()
+            },
+        };
+    }
+
+    // Weight of a sample at `offset` pixels from a pixel center.
+    pub fn eval(&self, offset: glm::Vec2) -> f32
+    {
+        return self.eval_1d(offset.x) * self.eval_1d(offset.y);
+    }
+
+    // How many pixels beyond its own a sample can reach.
+    pub fn padding(&self) -> u32
+    {
+        return ((self.radius + 0.5f32).ceil() as u32).max(1) - 1;
+    }
+}
@@ -0,0 +1,155 @@
+use crate::distribution::Distribution1D;
+use crate::disney::luminance;
+use crate::triangle::Triangle;
+
+pub struct LightSample
+{
+    pub direction: glm::Vec3,
+    pub distance: f32,
+    pub emission: glm::Vec3,
+    // Solid angle pdf as seen from the shading point.
+    pub pdf: f32,
+}
+
+// Emissive triangles, picked proportional to their emitted power.
+pub struct Lights
+{
+    pub triangles: Vec<u32>,
+    pub emission: Vec<glm::Vec3>,
+    distribution: Option<Distribution1D>,
+    total_power: f32,
+}
+
+pub fn power_heuristic(f_pdf: f32, g_pdf: f32) -> f32
+{
+    let f2 = f_pdf * f_pdf;
+    let g2 = g_pdf * g_pdf;
+    if f2 + g2 <= 0f32
+    {
+        return 0f32;
+    }
+
+    return f2 / (f2 + g2);
+}
+
+fn triangle_area(triangle: &Triangle) -> f32
+{
+    return glm::length(glm::cross(triangle.v1.pos - triangle.v0.pos, triangle.v2.pos - triangle.v0.pos)) * 0.5f32;
+}
+
+// Uniformly distributed point on the triangle.
+fn sample_triangle(triangle: &Triangle, u: glm::Vec2) -> glm::Vec3
+{
+    let su0 = u.x.sqrt();
+    let b0 = 1f32 - su0;
+    let b1 = u.y * su0;
+
+    return triangle.v0.pos * b0 + triangle.v1.pos * b1 + triangle.v2.pos * (1f32 - b0 - b1);
+}
+
+impl Lights
+{
+    pub fn new() -> Lights
+    {
+        Lights
+        {
+            triangles: Vec::new(),
+            emission: Vec::new(),
+            distribution: None,
+            total_power: 0f32,
+        }
+    }
+
+    pub fn add(&mut self, triangle: u32, emission: glm::Vec3)
+    {
+        self.triangles.push(triangle);
+        self.emission.push(emission);
+    }
+
+    pub fn len(&self) -> usize
+    {
+        return self.triangles.len();
+    }
+
+    pub fn can_sample(&self) -> bool
+    {
+        return self.distribution.is_some();
+    }
+
+    pub fn clear(&mut self)
+    {
+        *self = Lights::new();
+    }
+
+    // Moving lights are weighted by their area at `time`. The pdf relies on the area not changing over time,
+    // so lights that are scaled during the motion are slightly biased.
+    pub fn build(&mut self, triangles: &[Triangle], time: f32)
+    {
+        let power: Vec<f32> = self.triangles.iter().zip(self.emission.iter())
+            .map(|(&idx, &emission)| triangle_area(&triangles[idx as usize].at_time(time)) * luminance(emission))
+            .collect();
+
+        self.total_power = power.iter().sum();
+        self.distribution = if self.total_power > 0f32 { Some(Distribution1D::new(&power)) } else { None };
+    }
+
+    pub fn sample(&self, triangles: &[Triangle], pos: glm::Vec3, u_select: f32, u: glm::Vec2, time: f32) -> Option<LightSample>
+    {
+        let distribution = self.distribution.as_ref()?;
+        let (light_idx, pmf) = distribution.sample_discrete(u_select);
+        if pmf <= 0f32
+        {
+            return None;
+        }
+
+        let triangle = triangles[self.triangles[light_idx] as usize].at_time(time);
+        let light_pos = sample_triangle(&triangle, u);
+        let to_light = light_pos - pos;
+        let distance = glm::length(to_light);
+        if distance <= 0f32
+        {
+            return None;
+        }
+
+        let direction = to_light / distance;
+
+        // Lights only emit from their front face, like the intersection only hits front faces.
+        let normal = glm::normalize(glm::cross(triangle.v1.pos - triangle.v0.pos, triangle.v2.pos - triangle.v0.pos));
+        let cos_light = -glm::dot(normal, direction);
+        if cos_light <= 0f32
+        {
+            return None;
+        }
+
+        let area_pdf = pmf / triangle_area(&triangle);
+
+        Some(LightSample
+        {
+            direction,
+            distance,
+            emission: self.emission[light_idx],
+            pdf: area_pdf * distance * distance / cos_light,
+        })
+    }
+
+    // Solid angle pdf of `sample` picking the point `light_pos` with the given emission from `pos`.
+    // The area term cancels since lights are picked proportional to area times luminance.
+    pub fn pdf(&self, emission: glm::Vec3, pos: glm::Vec3, light_pos: glm::Vec3, light_normal: glm::Vec3) -> f32
+    {
+        if self.distribution.is_none()
+        {
+            return 0f32;
+        }
+
+        let to_light = light_pos - pos;
+        let distance2 = glm::dot(to_light, to_light);
+        let cos_light = glm::dot(light_normal, to_light).abs() / distance2.sqrt();
+        if cos_light <= 0f32
+        {
+            return 0f32;
+        }
+
+        let area_pdf = luminance(emission) / self.total_power;
+        return area_pdf * distance2 / cos_light;
+    }
+}
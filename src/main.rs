@@ -1,9 +1,12 @@
+#[cfg(feature = "preview")]
 extern crate sdl2;
 extern crate glm;
 extern crate oidn;
 extern crate image;
 
 mod application;
+#[cfg(feature = "preview")]
+mod window;
 mod scene;
 mod resource_manager;
 mod shape;
@@ -14,9 +17,30 @@ mod random;
 mod disney;
 mod texture;
 mod camera;
+mod light;
+mod distribution;
+mod environment;
+mod settings;
+mod transform;
+mod scene_file;
+mod cli;
+mod sampler;
+mod tile;
+mod checkpoint;
+mod binary;
+mod film;
+mod filter;
+mod output;
+mod tonemap;
+mod color;
+mod aov;
+mod animation;
+#[cfg(test)]
+mod test_util;
 
-use rand::rngs::SmallRng as RandGenerator;
 use std::time::{Duration, Instant};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use application::*;
 use model::*;
 use random::*;
@@ -24,102 +48,191 @@ use scene::*;
 use texture::*;
 use material::*;
 use camera::*;
-use rayon::prelude::*;
 use disney::*;
-use image::GenericImageView;
+use light::power_heuristic;
+use settings::*;
+use scene_file::*;
+use light::Lights;
+use environment::Environment;
+use cli::*;
+use sampler::*;
+use tile::*;
+use checkpoint::*;
+use film::{FilmPixel, RawFilm, SplatTile};
+use aov::*;
+use output::*;
+use tonemap::DisplayTransform;
 
-static EPSILON: f32 = 0.0001f32;
 static ACCUMULATE: bool = true;
-static JITTER_AA:bool = true;
 static SHOW_FPS: bool = false;
-static NUM_THREADS: u32 = 8;
-static RAY_DEPTH: u32 = 3;
-static DENOISE: bool = true;
-static GBUFFER_SAMPLES: u32 = 300;
-static SAMPLES_BEFORE_DENOISE: u32 = 100;
-static USE_EXTENDED_DENOISING: bool = true;
+static DEFAULT_SCENE: &str = "test.toml";
+static EXIT_FAILURE: i32 = 1;
+static EXIT_USAGE: i32 = 2;
+// The outputs were written but the render was cut short by the time limit or by closing the window.
+static EXIT_TIME_LIMIT: i32 = 3;
+static EXIT_CANCELLED: i32 = 4;
+
+// Sampler dimensions: pixel jitter, shutter time and lens first, then a fixed block per bounce (lobe selection,
+// BSDF direction, light selection, light position and Russian roulette).
+static CAMERA_DIMENSIONS: u32 = 5;
+static BOUNCE_DIMENSIONS: u32 = 7;
 
 struct MyApp
 {
     fps_start: Instant,
-    app_start: Instant,
+    render_start: Instant,
     frame: u32,
     accumulation_idx: u32,
-    camera: Camera,
+    // Filter weighted sums of the samples, resolved into the back buffer after every pass.
+    film: Vec<FilmPixel>,
+    // Sample count and luminance variance per pixel, drives adaptive sampling.
+    pixel_stats: Vec<PixelStats>,
+    // Pixels that still need samples after the last pass.
+    active_pixels: usize,
+    // Mean relative error over all pixels, only estimated once every pixel has enough samples.
+    noise_level: f32,
+    // Checkpoint to continue from.
+    resume: Option<String>,
+    // Back buffer size while framing a shot in the preview window, the output resolution when headless.
+    preview_size: (u32, u32),
+    last_checkpoint: Instant,
+    // Settings of the frame being rendered, and of the whole sequence they are taken from.
+    settings: RenderSettings,
+    sequence: RenderSettings,
+    // Exit status of the frames written so far.
+    status: i32,
+    description: SceneDescription,
+    camera: CameraAnimation,
     scene: SceneGraph,
     material_manager: MaterialManager,
     texture_manager: TextureManager,
     model_manager: ModelManager,
 }
 
-fn calculate_gbuffers(albedo: &mut Pixel, normal: &mut Pixel, pos: glm::Vec2, camera: &Camera, scene: &SceneGraph, material_manager: &MaterialManager, texture_manager: &TextureManager, accum_idx: u32, rng: &mut RandGenerator)
+// A pixel is done once it has all its samples, or with adaptive sampling when its error is below the threshold.
+fn is_converged(stats: &PixelStats, settings: &RenderSettings) -> bool
 {
-    let pixel_size = glm::vec2(1f32 / camera.viewport_width as f32, 1f32 / camera.viewport_height as f32);
-    let pixel_pos = pos * pixel_size;
+    if stats.count >= settings.samples
+    {
+        return true;
+    }
 
-    let jitter = if JITTER_AA { pixel_size * (next_rand_v2(rng) - 0.5)} else { glm::vec2(0f32, 0f32) };
-    let lens_uv  = next_rand_v2(rng);
-    let pixel_uv = pixel_pos + jitter;
+    return settings.adaptive_sampling && stats.count >= settings.adaptive_min_samples && stats.relative_error() < settings.adaptive_threshold;
+}
+
+// Film position of a camera sample in pixels, pixel centers are at integer coordinates.
+fn film_position(x: u32, y: u32, settings: &RenderSettings, sampler: &mut dyn Sampler) -> glm::Vec2
+{
+    // Drawn even without jitter so the lens and bounces always use the same sampler dimensions.
+    let pixel_sample = sampler.next_2d();
+    let jitter = if settings.jitter_aa { pixel_sample - 0.5 } else { glm::vec2(0f32, 0f32) };
+
+    return glm::vec2(x as f32, y as f32) + jitter;
+}
+
+// Traces a camera ray and accumulates its first hit into the AOV pixels, one per entry of `aovs`.
+fn calculate_aovs(pixels: &mut [&mut Pixel], aovs: &[Aov], film_pos: glm::Vec2, time: f32, camera: &CameraAnimation, scene: &SceneGraph, material_manager: &MaterialManager, texture_manager: &TextureManager, accum_idx: u32, sampler: &mut dyn Sampler)
+{
+    let camera = camera.at(time);
+    let pixel_size = glm::vec2(1f32 / camera.viewport_width as f32, 1f32 / camera.viewport_height as f32);
+    let pixel_uv = film_pos * pixel_size;
+    let lens_uv  = sampler.next_2d();
 
     let mut origin = glm::vec3(0f32, 0f32, 0f32);
     let mut direction = glm::vec3(0f32, 0f32, 0f32);
-    generate_camera_ray(pixel_uv, lens_uv, &mut origin, &mut direction, camera);
+    let has_ray = generate_camera_ray(pixel_uv, lens_uv, &mut origin, &mut direction, &camera);
 
-    let mut ray_albedo = glm::vec3(0f32, 0f32, 0f32);
-    let mut ray_normal = glm::vec3(0f32, 0f32, 0f32);
+    let hit = if has_ray { scene.traverse(origin, direction, time) } else { None };
 
-    let hit = scene.traverse(origin, direction);
-
-    if let Some(hit) = &hit
+    let albedo = match &hit
     {
-        let mut material = *material_manager.get(&hit.material_id).unwrap().clone();
+        Some(hit) => get_surface_material(hit, material_manager, texture_manager).color,
+        None if has_ray => scene.environment.eval(direction),
+        None => glm::vec3(0f32, 0f32, 0f32),
+    };
 
-        ray_albedo = material.color;
-        ray_normal = hit.normal;
-    }
-    else
+    for (pixel, aov) in pixels.iter_mut().zip(aovs)
     {
-        ray_albedo = glm::vec3(0.7f32, 0.7f32, 0.7f32);
-    }
-
-    let prev_albedo = pixel_to_vec3(albedo);
-    let albedo_result = (prev_albedo * accum_idx as f32 + ray_albedo) / (accum_idx as f32 + 1f32);
-    *albedo = vec3_to_pixel(&albedo_result);
+        let value = aov.evaluate(hit.as_ref(), albedo, &camera);
 
-    let prev_normal = pixel_to_vec3(normal);
-    let normal_result = (prev_normal * accum_idx as f32 + ray_normal) / (accum_idx as f32 + 1f32);
-    *normal = vec3_to_pixel(&normal_result);
+        if aov.is_filtered()
+        {
+            let prev = pixel_to_vec3(pixel);
+            let result = (prev * accum_idx as f32 + value) / (accum_idx as f32 + 1f32);
+            **pixel = vec3_to_pixel(&result);
+        }
+        else if accum_idx == 0
+        {
+            **pixel = vec3_to_pixel(&value);
+        }
+    }
 }
 
-fn parse_pixel(pixel: &mut Pixel, pos: glm::Vec2, camera: &Camera, scene: &SceneGraph, material_manager: &MaterialManager, texture_manager: &TextureManager, accum_idx: u32, rng: &mut RandGenerator)
+// Traces one path through the film position and returns its radiance.
+fn parse_pixel(film_pos: glm::Vec2, time: f32, settings: &RenderSettings, camera: &CameraAnimation, scene: &SceneGraph, material_manager: &MaterialManager, texture_manager: &TextureManager, sampler: &mut dyn Sampler) -> glm::Vec3
 {
+    let camera = camera.at(time);
     let pixel_size = glm::vec2(1f32 / camera.viewport_width as f32, 1f32 / camera.viewport_height as f32);
-    let pixel_pos = pos * pixel_size;
-
-    let jitter = if JITTER_AA { pixel_size * (next_rand_v2(rng) - 0.5)} else { glm::vec2(0f32, 0f32) };
-    let lens_uv  = next_rand_v2(rng);
-    let pixel_uv = pixel_pos + jitter;
+    let pixel_uv = film_pos * pixel_size;
+    let lens_uv  = sampler.next_2d();
 
     let mut origin = glm::vec3(0f32, 0f32, 0f32);
     let mut direction = glm::vec3(0f32, 0f32, 0f32);
-    generate_camera_ray(pixel_uv, lens_uv, &mut origin, &mut direction, camera);
+    if !generate_camera_ray(pixel_uv, lens_uv, &mut origin, &mut direction, &camera)
+    {
+        return glm::vec3(0f32, 0f32, 0f32);
+    }
 
     let mut ray_color = glm::vec3(0f32, 0f32, 0f32);
     let mut throughput = glm::vec3(1f32, 1f32, 1f32);
+    // Pdf of the BSDF sample that spawned the current ray, zero for camera rays.
+    let mut bsdf_pdf = 0f32;
+    let mut diffuse_bounces = 0u32;
+    let mut specular_bounces = 0u32;
 
-    'recursive_trace: for _ in 0..RAY_DEPTH
+    'recursive_trace: for depth in 0..settings.max_depth
     {
-        let hit = scene.traverse(origin, direction);
+        sampler.set_dimension(CAMERA_DIMENSIONS + depth * BOUNCE_DIMENSIONS);
+
+        let hit = scene.traverse(origin, direction, time);
         let v = -direction;
 
         if let Some(hit) = &hit
         {
             let material = get_surface_material(&hit, &material_manager, &texture_manager);
 
-            //let color = disney::direct_lighting(&scene, &hit, &material, v) * throughput;
-            let color = glm::vec3(0f32, 0f32, 0f32);
+            // Emission found through BSDF sampling is weighted against the light sampling of the previous vertex.
+            let emission_weight = if bsdf_pdf > 0f32
+            {
+                power_heuristic(bsdf_pdf, scene.light_pdf(material.emission, origin, hit.pos, hit.geometric_normal))
+            }
+            else
+            {
+                1f32
+            };
+            ray_color = ray_color + throughput * material.emission * emission_weight;
+
+            if depth + 1 == settings.max_depth
+            {
+                break 'recursive_trace;
+            }
+
+            // The bounce is picked before the light sample, a path that ends on its lobe's limit can't find the light
+            // through BSDF sampling so the light sample gets the full weight.
+            let (bsdf_dir, lobe) = disney::sample(&hit, &material, v, sampler);
+
+            let within_limit = match lobe
+            {
+                Lobe::Diffuse => { diffuse_bounces += 1; diffuse_bounces <= settings.max_diffuse_depth },
+                Lobe::Specular => { specular_bounces += 1; specular_bounces <= settings.max_specular_depth },
+            };
 
-            let bsdf_dir = disney::sample(&hit, &material, v, rng);
+            ray_color = ray_color + throughput * disney::direct_lighting(&scene, &hit, &material, v, within_limit, sampler);
+
+            if !within_limit
+            {
+                break 'recursive_trace;
+            }
 
             let l = bsdf_dir;
             let h = glm::normalize(v + l);
@@ -140,25 +253,36 @@ fn parse_pixel(pixel: &mut Pixel, pos: glm::Vec2, camera: &Camera, scene: &Scene
                 break 'recursive_trace;
             }
 
-            ray_color = glm::clamp(color, glm::vec3(0f32, 0f32, 0f32), glm::vec3(2f32, 2f32, 2f32));
+            // Russian roulette, paths that carry little energy are terminated and survivors are reweighted.
+            if depth >= settings.min_depth
+            {
+                let survive_prob = throughput.x.max(throughput.y).max(throughput.z).min(0.95f32);
+                if sampler.next_1d() >= survive_prob
+                {
+                    break 'recursive_trace;
+                }
+
+                throughput = throughput / survive_prob;
+            }
+
+            bsdf_pdf = pdf;
             origin = hit.pos + (bsdf_dir * EPSILON);
             direction = bsdf_dir;
         }
         else
         {
-            ray_color = glm::clamp(throughput * glm::vec3(0.7f32, 0.7f32, 0.7f32), glm::vec3(0f32, 0f32, 0f32), glm::vec3(2f32, 2f32, 2f32));
+            let environment_weight = if bsdf_pdf > 0f32 { power_heuristic(bsdf_pdf, scene.environment_pdf(direction)) } else { 1f32 };
+            ray_color = ray_color + throughput * scene.environment.eval(direction) * environment_weight;
             break 'recursive_trace;
         }
     }
 
-    let prev = pixel_to_vec3(pixel);
-    let result = (prev * accum_idx as f32 + ray_color) / (accum_idx as f32 + 1f32);
-    *pixel = vec3_to_pixel(&result);
+    return ray_color;
 }
 
 impl MyApp
 {
-    fn calc_fps(&mut self)
+    fn calc_fps(&mut self, pass_samples: u32)
     {
         if SHOW_FPS
         {
@@ -175,233 +299,738 @@ impl MyApp
 
         if ACCUMULATE
         {
-            self.accumulation_idx += 1;
+            self.accumulation_idx += pass_samples;
         }
     }
 }
 
-impl Renderer for MyApp
+impl MyApp
 {
-    fn init(&mut self, app: &mut Application)
+    // Normalizes the splatted film into the back buffer.
+    fn resolve_film(&self, app: &mut Application)
+    {
+        for (pixel, film) in app.back_buffer.pixels.iter_mut().zip(&self.film)
+        {
+            *pixel = film.resolve();
+        }
+    }
+
+    // Renders all AOVs in one pass, every tile of the pass covers the same pixels of each buffer.
+    fn render_aovs(&self, buffers: &mut [AovBuffer], width: u32, height: u32)
     {
-        rayon::ThreadPoolBuilder::new().num_threads(NUM_THREADS as usize).build_global().unwrap();
+        let aovs: Vec<Aov> = buffers.iter().map(|buffer| buffer.aov).collect();
 
-        let mat_0 = self.material_manager.load(&materials::GLOSSY_WHITE);
-        let _ = self.material_manager.load(&materials::GREEN);
-        let _ = self.material_manager.load(&materials::GLOSSY_ORANGE);
-        let _ = self.material_manager.load(&materials::BLUE);
+        let mut tiles: Vec<Vec<TileView<Pixel>>> = Vec::new();
+        for buffer in buffers.iter_mut()
+        {
+            for (i, view) in split_into_tiles(&mut buffer.pixels, width, height, self.settings.tile_size, self.settings.tile_order).into_iter().enumerate()
+            {
+                if tiles.len() <= i
+                {
+                    tiles.push(Vec::new());
+                }
+                tiles[i].push(view);
+            }
+        }
 
-        load_textures_of_material(&mat_0, &mut self.material_manager, &mut self.texture_manager);
+        render_tiles(tiles, |mut views|
+        {
+            let mut sampler = create_sampler(&self.settings, width, height, self.settings.seed ^ GBUFFER_STREAM);
+            let tile = views[0].tile;
 
-        let model_handle = self.model_manager.load("test.fbx");
-        let model = self.model_manager.get(&model_handle);
+            for y in tile.y0..tile.y1
+            {
+                for x in tile.x0..tile.x1
+                {
+                    let mut pixels: Vec<&mut Pixel> = views.iter_mut().map(|view| view.get_mut(x, y)).collect();
+
+                    for sample in 0..self.settings.gbuffer_samples
+                    {
+                        sampler.start_pixel_sample(x, y, sample);
+                        let film_pos = film_position(x, y, &self.settings, &mut *sampler);
+                        let time = self.settings.shutter_time(sampler.next_1d());
+                        calculate_aovs(&mut pixels, &aovs, film_pos, time, &self.camera, &self.scene, &self.material_manager, &self.texture_manager, sample, &mut *sampler);
+                    }
+                }
+            }
+        });
+    }
 
-        let model_matrix = glm::mat3(
-            1f32, 0f32, 0f32,
-            0f32, 1f32, 0f32,
-            0f32, 0f32, 1f32,
-        );
+    fn save_checkpoint(&mut self, app: &Application) -> Result<(), String>
+    {
+        let path = match &self.settings.checkpoint
+        {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        // Only a render at the output resolution can be resumed.
+        if self.is_preview()
+        {
+            return Ok(());
+        }
 
-        let mut material_id = 0u32;
-        for mesh in &model.unwrap().meshes
+        let header = CheckpointHeader
         {
-            for i in (0..mesh.indices.len()).step_by(3)
-            {
-                let mut v0 = mesh.vertices[mesh.indices[i + 0] as usize];
-                let mut v1 = mesh.vertices[mesh.indices[i + 1] as usize];
-                let mut v2 = mesh.vertices[mesh.indices[i + 2] as usize];
+            fingerprint: self.settings.render_fingerprint(&self.description, &self.camera),
+            width: app.back_buffer.width,
+            height: app.back_buffer.height,
+            accumulation_idx: self.accumulation_idx,
+            active_pixels: self.active_pixels as u64,
+            noise_level: self.noise_level,
+            elapsed: self.render_start.elapsed().as_secs_f64(),
+        };
+
+        save_checkpoint(path, &header, &self.film, &self.pixel_stats)?;
+        self.last_checkpoint = Instant::now();
+
+        println!("Saved Checkpoint: {} ({} samples)", path, self.accumulation_idx);
+        return Ok(());
+    }
 
-                v0.pos = model_matrix * v0.pos;
-                v1.pos = model_matrix * v1.pos;
-                v2.pos = model_matrix * v2.pos;
+    fn resume_checkpoint(&mut self, path: &str, app: &mut Application) -> Result<(), String>
+    {
+        let fingerprint = self.settings.render_fingerprint(&self.description, &self.camera);
+        let (header, film, stats) = load_checkpoint(path, fingerprint, app.back_buffer.width, app.back_buffer.height)?;
+
+        self.film = film;
+        self.pixel_stats = stats;
+        self.resolve_film(app);
+        self.accumulation_idx = header.accumulation_idx;
+        self.active_pixels = header.active_pixels as usize;
+        self.noise_level = header.noise_level;
+
+        // The time limit covers the whole render, including the time before the checkpoint.
+        let now = Instant::now();
+        self.render_start = now.checked_sub(Duration::from_secs_f64(header.elapsed)).unwrap_or(now);
+
+        println!("Resumed Checkpoint: {} ({} samples)", path, self.accumulation_idx);
+        return Ok(());
+    }
+}
 
-                self.scene.add_tri(v0, v1, v2, material_id);
-            }
+impl Renderer for MyApp
+{
+    fn init(&mut self, app: &mut Application)
+    {
+        rayon::ThreadPoolBuilder::new().num_threads(self.settings.threads as usize).build_global().unwrap();
 
-            material_id += 1;
+        if let Err(e) = build_scene(&self.description, &mut self.scene, &mut self.material_manager, &mut self.texture_manager, &mut self.model_manager)
+        {
+            println!("Failed to build scene: {}", e);
+            std::process::exit(EXIT_FAILURE);
         }
 
         println!("#################################");
-        println!("Thread Count: {}", NUM_THREADS);
-        println!("Rays per frame: {}", app.back_buffer.width * app.back_buffer.height * RAY_DEPTH);
-        println!("Bounces: {}", RAY_DEPTH - 1);
+        println!("Thread Count: {}", self.settings.threads);
+        println!("Rays per frame: {}", app.back_buffer.width * app.back_buffer.height * self.settings.max_depth);
+        println!("Bounces: {}", self.settings.max_depth - 1);
+        println!("Seed: {}", self.settings.seed);
+        println!("Sampler: {:?}", self.settings.sampler);
         println!("Triangles: {}", self.scene.tri_count());
+        println!("Emissive Triangles: {}", self.scene.lights.len());
+        if self.sequence.is_sequence()
+        {
+            println!("Frames: {}..{}", self.sequence.frame_start, self.sequence.frame_end());
+        }
         println!("#################################");
 
-        self.scene.build();
+        self.preview_size = (app.back_buffer.width, app.back_buffer.height);
+        self.start_frame(app, self.sequence.frame_start);
+
+        if let Some(path) = self.resume.clone()
+        {
+            self.set_render_size(app, self.settings.width, self.settings.height);
+            if let Err(e) = self.resume_checkpoint(&path, app)
+            {
+                println!("{}", e);
+                std::process::exit(EXIT_FAILURE);
+            }
+        }
+    }
+
+    // Only the preview follows the window size, the outputs keep the resolution from the settings.
+    fn resize(&mut self, app: &mut Application, width: u32, height: u32)
+    {
+        self.preview_size = self.settings.preview_size(width, height);
+        self.set_render_size(app, self.preview_size.0, self.preview_size.1);
+    }
+
+    // The fly camera starts from the pose at the beginning of the shutter interval and drops the animation.
+    // Moving it goes back to the preview size.
+    fn move_camera(&mut self, app: &mut Application, input: &CameraInput)
+    {
+        self.camera.fly(self.settings.shutter_time(0f32), input);
+        self.set_render_size(app, self.preview_size.0, self.preview_size.1);
+    }
+
+    fn display_transform(&self) -> DisplayTransform
+    {
+        return self.settings.display_transform();
     }
 
     fn render(&mut self, app: &mut Application)
     {
-        let num_pixels = app.back_buffer.width * app.back_buffer.height;
+        // The stop conditions apply to each frame of a sequence, `stop_reason` only ends it after the last one.
+        // A finished preview at another size is rendered again at the output resolution before it is written.
+        if let Some(reason) = self.frame_stop_reason()
+        {
+            if self.is_preview()
+            {
+                self.set_render_size(app, self.settings.width, self.settings.height);
+            }
+            else
+            {
+                self.finish_frame(app, reason);
+                self.start_frame(app, self.settings.frame_start + 1);
+            }
+        }
+
+        let width = app.back_buffer.width;
+        let height = app.back_buffer.height;
 
-        let bb_width = app.back_buffer.width.clone();
-        let pixels = &mut app.back_buffer.pixels;
+        // Several samples per pass keep the per tile overhead low, the last pass stops at the sample count.
+        let first_sample = self.accumulation_idx;
+        let pass_samples = self.settings.samples_per_pass.min(self.settings.samples.saturating_sub(first_sample)).max(1);
 
-        (0..num_pixels).into_par_iter().for_each(|i| unsafe
+        let active_pixels = AtomicUsize::new(0);
+
+        let tiles: Vec<_> = split_into_tiles(&mut self.pixel_stats, width, height, self.settings.tile_size, self.settings.tile_order).into_iter().enumerate().collect();
+        let (settings, camera, scene) = (&self.settings, &self.camera, &self.scene);
+        let (material_manager, texture_manager) = (&self.material_manager, &self.texture_manager);
+        let filter = self.settings.filter();
+        let splats = Mutex::new((0..tiles.len()).map(|_| None).collect::<Vec<Option<SplatTile>>>());
+
+        render_tiles(tiles, |(tile_idx, mut stats)|
         {
-            let mut rng = create_rand_generator();
+            let mut sampler = create_sampler(settings, width, height, settings.seed);
+            let mut splat = SplatTile::new(&stats.tile, filter.padding(), width, height);
+            let mut tile_active = 0;
 
-            let pixel_pos = glm::vec2(
-                i as f32 % bb_width as f32,
-                (i as f32 / bb_width as f32).floor()
-            );
+            for y in stats.tile.y0..stats.tile.y1
+            {
+                for x in stats.tile.x0..stats.tile.x1
+                {
+                    let pixel_stats = stats.get_mut(x, y);
+                    if is_converged(pixel_stats, settings)
+                    {
+                        continue;
+                    }
+
+                    let last_sample = (pixel_stats.count + pass_samples).min(settings.samples);
+                    while pixel_stats.count < last_sample
+                    {
+                        sampler.start_pixel_sample(x, y, pixel_stats.count);
+                        let film_pos = film_position(x, y, settings, &mut *sampler);
+                        let time = settings.shutter_time(sampler.next_1d());
+                        let color = parse_pixel(film_pos, time, settings, camera, scene, material_manager, texture_manager, &mut *sampler);
+                        pixel_stats.add(luminance(color));
+                        splat.splat(film_pos, color, &filter);
+                    }
+
+                    if !is_converged(pixel_stats, settings)
+                    {
+                        tile_active += 1;
+                    }
+                }
+            }
 
-            let pixel = (pixels.as_ptr() as *mut Pixel).offset(i as isize);
-            parse_pixel(&mut *pixel, pixel_pos, &self.camera, &self.scene, &self.material_manager, &self.texture_manager, self.accumulation_idx, &mut rng);
+            splats.lock().unwrap()[tile_idx] = Some(splat);
+            active_pixels.fetch_add(tile_active, Ordering::Relaxed);
         });
 
-        self.calc_fps();
+        // Padded tiles overlap, adding them in tile order keeps the sums on their borders the same whichever thread finishes first.
+        for splat in splats.into_inner().unwrap().iter().flatten()
+        {
+            splat.add_to(&mut self.film, width);
+        }
 
-        // image denoising
-        if DENOISE
+        self.active_pixels = active_pixels.into_inner();
+        self.resolve_film(app);
+
+        self.calc_fps(pass_samples);
+
+        if self.accumulation_idx >= self.settings.adaptive_min_samples.max(2)
+        {
+            let total_error: f64 = self.pixel_stats.iter().map(|stats| stats.relative_error() as f64).sum();
+            self.noise_level = (total_error / self.pixel_stats.len() as f64) as f32;
+        }
+
+        if (first_sample / 25) != (self.accumulation_idx / 25) && self.accumulation_idx <= self.settings.samples
         {
-            if (self.accumulation_idx % 25) == 0 && self.accumulation_idx <= SAMPLES_BEFORE_DENOISE
+            let elapsed = self.render_start.elapsed().as_secs_f32();
+            let percent = self.accumulation_idx as f32 / self.settings.samples as f32;
+            let mut remaining = elapsed * (1f32 / percent - 1f32);
+            if self.settings.time_limit > 0f32
             {
-                let elapsed = self.fps_start.elapsed().as_secs_f32();
-                let percent = self.accumulation_idx as f32 / SAMPLES_BEFORE_DENOISE as f32;
-                let remaining = elapsed * (1f32 / percent - 1f32);
-                println!("({:.2}%) \t Elapsed: {:.2} min \t ETA: {:.2} min", percent * 100f32, elapsed / 60f32, remaining / 60f32);
+                remaining = remaining.min((self.settings.time_limit - elapsed).max(0f32));
             }
+            println!("({:.2}%) \t Elapsed: {:.2} min \t ETA: {:.2} min", percent * 100f32, elapsed / 60f32, remaining / 60f32);
 
-            if self.accumulation_idx == SAMPLES_BEFORE_DENOISE
+            if self.settings.adaptive_sampling
             {
-                println!("Accumulation Finished");
+                let converged = 1f32 - self.active_pixels as f32 / self.pixel_stats.len() as f32;
+                println!("Converged pixels: {:.2}%", converged * 100f32);
+            }
+            if self.settings.noise_threshold > 0f32 && self.noise_level < std::f32::MAX
+            {
+                println!("Noise level: {:.4} (target {:.4})", self.noise_level, self.settings.noise_threshold);
+            }
+        }
 
-                // Get GBUFFERS
-                let mut albedo_pixels = vec![Pixel { r: 0f32, g: 0f32, b: 0f32, a: 0f32 }; (app.back_buffer.width * app.back_buffer.height) as usize];
-                let mut normal_pixels = vec![Pixel { r: 0f32, g: 0f32, b: 0f32, a: 0f32 }; (app.back_buffer.width * app.back_buffer.height) as usize];
+        if self.settings.checkpoint.is_some() && self.last_checkpoint.elapsed().as_secs_f32() >= self.settings.checkpoint_interval
+        {
+            if let Err(e) = self.save_checkpoint(app)
+            {
+                println!("{}", e);
+            }
+        }
+    }
 
-                if USE_EXTENDED_DENOISING
-                {
-                    println!("Generating GBuffers....");
+    fn stop_reason(&self) -> Option<StopReason>
+    {
+        let reason = self.frame_stop_reason()?;
+        if self.is_preview() || self.settings.frame_start < self.sequence.frame_end()
+        {
+            return None;
+        }
 
-                    for i in 0..GBUFFER_SAMPLES
-                    {
-                        (0..num_pixels).into_par_iter().for_each(|i| unsafe
-                        {
-                            let mut rng = create_rand_generator();
-
-                            let pixel_pos = glm::vec2(
-                                i as f32 % bb_width as f32,
-                                (i as f32 / bb_width as f32).floor()
-                            );
-
-                            let albedo = (albedo_pixels.as_ptr() as *mut Pixel).offset(i as isize);
-                            let normal = (normal_pixels.as_ptr() as *mut Pixel).offset(i as isize);
-                            calculate_gbuffers(&mut *albedo, &mut *normal, pixel_pos, &self.camera, &self.scene, &self.material_manager, &self.texture_manager, self.accumulation_idx, &mut rng);
-                        });
-                    }
+        return Some(reason);
+    }
 
-                    println!("Finished Generating GBuffers");
-                }
+    fn finish(&mut self, app: &mut Application, reason: StopReason) -> i32
+    {
+        self.finish_frame(app, reason);
+        return self.status;
+    }
+}
 
-                let input_img = f32vec_from_pixels(&app.back_buffer.pixels);
-                let input_albedo = f32vec_from_pixels(&albedo_pixels);
-                let input_normal = f32vec_from_pixels(&normal_pixels);
+impl MyApp
+{
+    // Resets the render state for a frame of the sequence. Loaded assets are kept, and so is the BVH unless
+    // something in the scene moves.
+    fn start_frame(&mut self, app: &mut Application, frame: u32)
+    {
+        self.settings = self.sequence.for_frame(frame);
 
-                let mut filter_output = vec![0.0f32; input_img.len()];
+        if self.scene.bvh.is_none() || self.scene.is_animated()
+        {
+            self.scene.build(self.settings.shutter_time(0f32), self.settings.shutter_time(1f32));
+        }
 
-                let device = oidn::Device::new();
-                let mut filter = oidn::RayTracing::new(&device);
+        if self.sequence.is_sequence()
+        {
+            println!("Frame {} ({}/{})", frame, frame - self.sequence.frame_start + 1, self.sequence.frame_end() - self.sequence.frame_start + 1);
+        }
 
-                filter.set_srgb(true);
-                filter.set_img_dims(app.back_buffer.width as usize, app.back_buffer.height as usize);
-                if USE_EXTENDED_DENOISING
+        self.restart(app);
+    }
+
+    // The preview renders at its own size until it is done, the frame is then rendered at the output resolution.
+    // The camera always has the size of the back buffer.
+    fn is_preview(&self) -> bool
+    {
+        return self.camera.width != self.settings.width || self.camera.height != self.settings.height;
+    }
+
+    fn set_render_size(&mut self, app: &mut Application, width: u32, height: u32)
+    {
+        if app.back_buffer.width != width || app.back_buffer.height != height
+        {
+            app.back_buffer = RenderTexture::new(width, height);
+            println!("Resolution: {}x{}", width, height);
+        }
+
+        self.restart(app);
+    }
+
+    // Throws away the samples taken so far.
+    fn restart(&mut self, app: &Application)
+    {
+        self.camera.width = app.back_buffer.width;
+        self.camera.height = app.back_buffer.height;
+        self.accumulation_idx = 0;
+        self.pixel_stats = vec![PixelStats::default(); (app.back_buffer.width * app.back_buffer.height) as usize];
+        self.film = vec![FilmPixel::default(); self.pixel_stats.len()];
+        self.active_pixels = self.pixel_stats.len();
+        self.noise_level = std::f32::MAX;
+        self.render_start = Instant::now();
+        self.last_checkpoint = self.render_start;
+    }
+
+    // The frame ends on whichever condition is met first.
+    fn frame_stop_reason(&self) -> Option<StopReason>
+    {
+        if self.accumulation_idx >= self.settings.samples || self.active_pixels == 0
+        {
+            return Some(StopReason::SampleTarget);
+        }
+        if self.settings.noise_threshold > 0f32 && self.noise_level < self.settings.noise_threshold
+        {
+            return Some(StopReason::NoiseThreshold);
+        }
+        if self.settings.time_limit > 0f32 && self.render_start.elapsed().as_secs_f32() >= self.settings.time_limit
+        {
+            return Some(StopReason::TimeLimit);
+        }
+
+        return None;
+    }
+
+    // Writes the outputs of the frame and records its exit status, a failure outlasts the other statuses.
+    fn finish_frame(&mut self, app: &mut Application, reason: StopReason)
+    {
+        // Only reached when the window is closed, the preview itself always goes on to the output resolution.
+        if self.is_preview()
+        {
+            println!("Preview closed before rendering at {}x{}, nothing was written", self.settings.width, self.settings.height);
+            if self.status == 0
+            {
+                self.status = EXIT_CANCELLED;
+            }
+            return;
+        }
+
+        println!("Accumulation Finished: {:?} after {} samples in {:.2} min", reason, self.accumulation_idx, self.render_start.elapsed().as_secs_f32() / 60f32);
+
+        let mut status = match reason
+        {
+            StopReason::SampleTarget | StopReason::NoiseThreshold => 0,
+            StopReason::TimeLimit => EXIT_TIME_LIMIT,
+            StopReason::Cancelled => EXIT_CANCELLED,
+        };
+
+        // An unfinished render can be continued later.
+        if reason == StopReason::TimeLimit || reason == StopReason::Cancelled
+        {
+            if let Err(e) = self.save_checkpoint(app)
+            {
+                println!("{}", e);
+                status = EXIT_FAILURE;
+            }
+        }
+
+        if let Err(e) = save_pixels(&self.settings.output, &app.back_buffer.pixels, app.back_buffer.width, app.back_buffer.height, self.settings.exr_precision, &self.settings.display_transform())
+        {
+            println!("{}", e);
+            status = EXIT_FAILURE;
+        }
+
+        if let Some(path) = &self.settings.sample_count_output
+        {
+            if let Err(e) = save_sample_counts(path, &self.pixel_stats, app.back_buffer.width, app.back_buffer.height, self.settings.samples)
+            {
+                println!("{}", e);
+                status = EXIT_FAILURE;
+            }
+        }
+
+        let width = app.back_buffer.width;
+        let height = app.back_buffer.height;
+
+        // The denoiser's guides are rendered by the AOV pass along with the requested AOVs.
+        let mut aovs = self.settings.aovs.clone();
+        if self.settings.denoise && self.settings.extended_denoising
+        {
+            for aov in &[Aov::Albedo, Aov::Normal]
+            {
+                if !aovs.contains(aov)
                 {
-                    filter.set_albedo(&input_albedo[..]);
-                    filter.set_normal(&input_normal[..]);
+                    aovs.push(*aov);
                 }
-                filter.execute(&input_img[..], &mut filter_output[..]).expect("Filter config error!");
-
-                if let Err(e) = device.get_error() {
-                    println!("Error denosing image: {}", e.1);
-                } else {
-                    // Save denoised image
-                    let mut out_denoised = Vec::new();
-                    for data in filter_output
-                    {
-                        out_denoised.push((data.powf(1f32 / 2.2) * 65535f32) as u16);
-                    }
-                    let denoised_u8 = unsafe { std::slice::from_raw_parts(out_denoised.as_ptr() as *mut u8, out_denoised.len() * 2) };
-                    image::save_buffer(&std::path::Path::new("image_denoised.png"), &denoised_u8[..], app.back_buffer.width, app.back_buffer.height, image::ColorType::Rgb16);
+            }
+        }
 
-                    let dir = std::env::current_dir().unwrap().to_str().unwrap().replace("\\", "/");
-                    println!("Saved Denoised File: file:///{}/{}", dir, "image_denoised.png");
+        let mut aov_buffers: Vec<AovBuffer> = aovs.iter().map(|&aov| AovBuffer::new(aov, width, height)).collect();
+        if !aov_buffers.is_empty()
+        {
+            println!("Rendering AOVs....");
+            self.render_aovs(&mut aov_buffers, width, height);
+            println!("Finished Rendering AOVs");
+        }
 
-                    save_pixels("image.png", &app.back_buffer.pixels, app.back_buffer.width, app.back_buffer.height);
-                    //save_pixels("g_albedo.png", &albedo_pixels, app.back_buffer.width, app.back_buffer.height);
-                    //save_pixels("g_normal.png", &normal_pixels, app.back_buffer.width, app.back_buffer.height);
+        let aov_pixels = |aov: Aov| aov_buffers.iter().find(|buffer| buffer.aov == aov).map(|buffer| &buffer.pixels);
 
-                    let mut img_denoised = image::open("image_denoised.png").unwrap();
-                    img_denoised = img_denoised.flipv();
-                    img_denoised.save("image_denoised.png");
-                }
+        // image denoising
+        if self.settings.denoise
+        {
+            let guides = if self.settings.extended_denoising { aov_pixels(Aov::Albedo).zip(aov_pixels(Aov::Normal)) } else { None };
+            let denoised = denoise(&app.back_buffer.pixels, guides, width, height)
+                .and_then(|denoised| save_denoised(&self.settings.denoised_output(), &denoised, width, height, self.settings.exr_precision, &self.settings.display_transform()));
+
+            if let Err(e) = denoised
+            {
+                println!("{}", e);
+                status = EXIT_FAILURE;
             }
         }
+
+        if !self.settings.aovs.is_empty()
+        {
+            let requested: Vec<&AovBuffer> = aov_buffers.iter().filter(|buffer| self.settings.aovs.contains(&buffer.aov)).collect();
+            if let Err(e) = save_aovs(&self.settings.aov_output, &requested, width, height, self.settings.aov_multilayer, self.settings.exr_precision)
+            {
+                println!("{}", e);
+                status = EXIT_FAILURE;
+            }
+        }
+
+        if let Some(path) = &self.settings.film_output
+        {
+            let fingerprint = self.settings.film_fingerprint(&self.description, &self.camera);
+            let film = RawFilm::from_film(app.back_buffer.width, app.back_buffer.height, fingerprint, self.settings.seed, &self.film, &self.pixel_stats);
+            if let Err(e) = film.save(path)
+            {
+                println!("{}", e);
+                status = EXIT_FAILURE;
+            }
+        }
+
+        if status == EXIT_FAILURE || self.status == 0
+        {
+            self.status = status;
+        }
     }
 }
 
-fn save_pixels(path: &str, pixels: &Vec<Pixel>, width: u32, height: u32)
+// Runs Open Image Denoise on the image, optionally guided by albedo and normal buffers. Returns packed RGB.
+fn denoise(pixels: &Vec<Pixel>, guides: Option<(&Vec<Pixel>, &Vec<Pixel>)>, width: u32, height: u32) -> Result<Vec<f32>, String>
 {
-    // Save non-denoised image
-    let mut vec_u16 = Vec::new();
-    for pixel in pixels
+    let input_img = f32vec_from_pixels(pixels);
+    let mut filter_output = vec![0.0f32; input_img.len()];
+
+    let device = oidn::Device::new();
+    let mut filter = oidn::RayTracing::new(&device);
+
+    // The film is unclamped linear radiance, the display transform is only applied when writing a PNG.
+    filter.set_hdr(true);
+    filter.set_img_dims(width as usize, height as usize);
+
+    let input_albedo;
+    let input_normal;
+    if let Some((albedo, normal)) = guides
+    {
+        // The albedo guide has to be in [0, 1], rays that miss carry the HDR environment.
+        input_albedo = f32vec_from_pixels(albedo).iter().map(|v| v.max(0f32).min(1f32)).collect::<Vec<f32>>();
+        input_normal = f32vec_from_pixels(normal);
+        filter.set_albedo(&input_albedo[..]);
+        filter.set_normal(&input_normal[..]);
+    }
+    filter.execute(&input_img[..], &mut filter_output[..]).map_err(|e| format!("Filter config error: {:?}", e))?;
+
+    if let Err(e) = device.get_error()
+    {
+        return Err(format!("Error denosing image: {}", e.1));
+    }
+
+    return Ok(filter_output);
+}
+
+fn save_denoised(path: &str, denoised: &Vec<f32>, width: u32, height: u32, precision: ExrPrecision, display: &DisplayTransform) -> Result<(), String>
+{
+    save_rgb(path, denoised, width, height, precision, display)?;
+
+    let dir = std::env::current_dir().unwrap().to_str().unwrap().replace("\\", "/");
+    println!("Saved Denoised File: file:///{}/{}", dir, path);
+    return Ok(());
+}
+
+// Combines films from independent renders into one image, or into one film when the output is a .film file.
+fn run_merge(cmd: &MergeCommand) -> i32
+{
+    let mut film = match RawFilm::load(&cmd.inputs[0])
+    {
+        Ok(film) => film,
+        Err(e) =>
+        {
+            println!("{}", e);
+            return EXIT_FAILURE;
+        }
+    };
+
+    for path in &cmd.inputs[1..]
+    {
+        if let Err(e) = RawFilm::load(path).and_then(|other| film.merge(&other).map_err(|e| format!("Film '{}': {}", path, e)))
+        {
+            println!("{}", e);
+            return EXIT_FAILURE;
+        }
+    }
+
+    println!("Merged {} films ({} renders)", cmd.inputs.len(), film.seeds.len());
+
+    if output_extension(&cmd.output) == FILM_EXTENSION
+    {
+        if let Err(e) = film.save(&cmd.output)
+        {
+            println!("{}", e);
+            return EXIT_FAILURE;
+        }
+        return 0;
+    }
+
+    let pixels = film.resolve();
+    if let Err(e) = save_pixels(&cmd.output, &pixels, film.width, film.height, cmd.exr_precision, &cmd.display)
     {
-        vec_u16.push((pixel.r.powf(1f32 / 2.2) * 65535f32).min(65535f32) as u16);
-        vec_u16.push((pixel.g.powf(1f32 / 2.2) * 65535f32).min(65535f32) as u16);
-        vec_u16.push((pixel.b.powf(1f32 / 2.2) * 65535f32).min(65535f32) as u16);
+        println!("{}", e);
+        return EXIT_FAILURE;
     }
-    let data_u8 = unsafe { std::slice::from_raw_parts(vec_u16.as_ptr() as *mut u8, vec_u16.len() * 2) };
 
-    image::save_buffer(&std::path::Path::new(path), &data_u8[..], width, height, image::ColorType::Rgb16).unwrap();
+    if cmd.denoise
+    {
+        let denoised = denoise(&pixels, None, film.width, film.height)
+            .and_then(|denoised| save_denoised(&suffixed_path(&cmd.output, "_denoised"), &denoised, film.width, film.height, cmd.exr_precision, &cmd.display));
 
-    let mut img_original = image::open(path).unwrap();
-    img_original = img_original.flipv();
-    img_original.save(path);
+        if let Err(e) = denoised
+        {
+            println!("{}", e);
+            return EXIT_FAILURE;
+        }
+    }
+
+    return 0;
+}
+
+fn save_pixels(path: &str, pixels: &Vec<Pixel>, width: u32, height: u32, precision: ExrPrecision, display: &DisplayTransform) -> Result<(), String>
+{
+    save_rgb(path, &f32vec_from_pixels(pixels), width, height, precision, display)?;
 
     let dir = std::env::current_dir().unwrap().to_str().unwrap().replace("\\", "/");
     println!("Saved File: file:///{}/{}", dir, path);
+    return Ok(());
+}
+
+// Writes the samples taken per pixel as a 16 bit grayscale image, white is `max_samples`.
+fn save_sample_counts(path: &str, stats: &Vec<PixelStats>, width: u32, height: u32, max_samples: u32) -> Result<(), String>
+{
+    if output_extension(path) == "png"
+    {
+        let image = image::ImageBuffer::from_fn(width, height, |x, y|
+        {
+            let count = stats[((height - 1 - y) * width + x) as usize].count;
+            image::Luma([((count as f32 / max_samples as f32).min(1f32) * 65535f32) as u16])
+        });
+
+        image.save(path).map_err(|e| format!("Failed to save sample counts '{}': {}", path, e))?;
+    }
+    else
+    {
+        save_gray(path, stats.iter().map(|s| s.count as f32).collect(), width, height)?;
+    }
+
+    println!("Saved Sample Counts: {}", path);
+    return Ok(());
 }
 
 // glam -> reciprocal???
 fn main()
 {
-    let back_buffer_width = 600;
-    let back_buffer_height = 600;
-
-    let fov = 45f32;
-    let aspect_ratio = back_buffer_width as f32 / back_buffer_height as f32;
-    let lens_dim = 0f32;
-    let focal_dist = 10f32;
-
-    let camera = Camera
-    {
-        pos: glm::vec3(0f32, 0f32, -10f32),
-        aspect_ratio,
-        up: glm::vec3(0f32, 1f32, 0f32),
-        half_fov: (0.5f32 * fov.to_radians()).tan(),
-        right: glm::vec3(-1f32, 0f32, 0f32),
-        lens_radius: 0.5f32 * lens_dim,
-        forward: glm::vec3(0f32, 0f32, 1f32),
-        focal_dist,
-        viewport_width: back_buffer_width,
-        viewport_height: back_buffer_height,
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(|arg| arg.as_str()) == Some("merge")
+    {
+        let cmd = match parse_merge_args(args.into_iter().skip(1))
+        {
+            Ok(cmd) => cmd,
+            Err(e) =>
+            {
+                println!("error: {}\n\n{}", e, MERGE_USAGE);
+                std::process::exit(EXIT_USAGE);
+            }
+        };
+
+        if cmd.help
+        {
+            println!("{}", MERGE_USAGE);
+            return;
+        }
+
+        std::process::exit(run_merge(&cmd));
+    }
+
+    let cmd = match parse_args(args.into_iter())
+    {
+        Ok(cmd) => cmd,
+        Err(e) =>
+        {
+            println!("error: {}\n\n{}", e, USAGE);
+            std::process::exit(EXIT_USAGE);
+        }
+    };
+
+    if cmd.help
+    {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let scene_path = cmd.scene.clone().unwrap_or(DEFAULT_SCENE.to_owned());
+
+    let description = match load_scene_description(&scene_path)
+    {
+        Ok(description) => description,
+        Err(e) =>
+        {
+            println!("{}", e);
+            std::process::exit(EXIT_FAILURE);
+        }
+    };
+
+    let mut settings = description.render.clone();
+    cmd.apply(&mut settings);
+
+    if let Err(e) = settings.validate()
+    {
+        println!("error: {}", e);
+        std::process::exit(EXIT_USAGE);
+    }
+
+    if settings.is_sequence() && cmd.resume.is_some()
+    {
+        println!("error: A sequence can't be resumed, render the remaining frames with --frames instead");
+        std::process::exit(EXIT_USAGE);
+    }
+
+    if cmd.headless && cmd.resolution_scale.is_some()
+    {
+        println!("error: --resolution-scale only applies to the preview window");
+        std::process::exit(EXIT_USAGE);
+    }
+
+    let camera = match description.active_camera().and_then(|camera| camera.to_camera_animation(settings.width, settings.height))
+    {
+        Ok(camera) => camera,
+        Err(e) =>
+        {
+            println!("{}", e);
+            std::process::exit(EXIT_FAILURE);
+        }
     };
 
     let scene = SceneGraph
     {
         triangles: Vec::new(),
         bvh: None,
+        environment: Environment::constant(glm::vec3(0f32, 0f32, 0f32)),
+        lights: Lights::new(),
     };
 
     let now = Instant::now();
 
     let mut app = MyApp{
         fps_start: now,
-        app_start: now,
+        render_start: now,
         frame: 0,
         accumulation_idx: 0,
+        film: Vec::new(),
+        pixel_stats: Vec::new(),
+        active_pixels: 0,
+        noise_level: std::f32::MAX,
+        resume: cmd.resume.clone(),
+        preview_size: (settings.width, settings.height),
+        last_checkpoint: now,
+        settings: settings.for_frame(settings.frame_start),
+        sequence: settings.clone(),
+        status: 0,
+        description,
         camera,
         scene,
         material_manager: MaterialManager::new(MaterialLoader{}),
@@ -409,6 +1038,97 @@ fn main()
         model_manager: ModelManager::new(ModelLoader{})
     };
 
-    AppBuilder::new("My Raytracer", back_buffer_width, back_buffer_height)
-        .start(&mut app);
+    // Without a display (or when built without the preview feature) render offline and exit.
+    if cfg!(not(feature = "preview")) || cmd.headless
+    {
+        std::process::exit(run_headless(settings.width, settings.height, &mut app));
+    }
+
+    #[cfg(feature = "preview")]
+    {
+        let (preview_width, preview_height) = settings.preview_size(settings.width, settings.height);
+        std::process::exit(window::AppBuilder::new("My Raytracer", settings.width, settings.height)
+            .render_size(preview_width, preview_height)
+            .keep_open(cmd.keep_open)
+            .fly_speed(cmd.fly_speed.unwrap_or(window::DEFAULT_FLY_SPEED))
+            .start(&mut app));
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // A square of the given half size centered on the Y axis, facing up or down.
+    fn square(scene: &mut SceneGraph, y: f32, size: f32, normal: glm::Vec3, material_id: u32, emission: Option<glm::Vec3>)
+    {
+        let vertex = |x: f32, z: f32| Vertex { pos: glm::vec3(x * size, y, z * size), normal, tangent: glm::vec3(1f32, 0f32, 0f32), bitangent: glm::vec3(0f32, 0f32, 1f32), uv: glm::vec2(0f32, 0f32) };
+        let (a, d) = (vertex(-1f32, -1f32), vertex(1f32, 1f32));
+        // Only the side the winding faces can be hit.
+        let (b, c) = if normal.y < 0f32 { (vertex(1f32, -1f32), vertex(-1f32, 1f32)) } else { (vertex(-1f32, 1f32), vertex(1f32, -1f32)) };
+
+        for &(v0, v1, v2) in &[(a, b, c), (c, b, d)]
+        {
+            match emission
+            {
+                Some(emission) => scene.add_light(v0, v1, v2, material_id, None, emission),
+                None => scene.add_tri(v0, v1, v2, material_id, None),
+            }
+        }
+    }
+
+    // Mean of the center pixel of a camera looking down through the back of a light onto a white floor.
+    fn render_floor(settings: &RenderSettings) -> f32
+    {
+        let mut material_manager = MaterialManager::new(MaterialLoader{});
+        let texture_manager = TextureManager::new(TextureLoader{});
+        let floor = material_manager.load(&Material { color: glm::vec3(1f32, 1f32, 1f32), ..Material::default() });
+        let light = material_manager.load(&Material { emission_strength: 1f32, ..Material::default() });
+
+        let mut scene = SceneGraph
+        {
+            triangles: Vec::new(),
+            bvh: None,
+            environment: Environment::constant(glm::vec3(0f32, 0f32, 0f32)),
+            lights: Lights::new(),
+        };
+        square(&mut scene, 0f32, 50f32, glm::vec3(0f32, 1f32, 0f32), floor, None);
+        square(&mut scene, 1f32, 1f32, glm::vec3(0f32, -1f32, 0f32), light, Some(glm::vec3(1f32, 1f32, 1f32)));
+        scene.build(0f32, 0f32);
+
+        let camera: CameraDescription = toml::from_str("name = \"top\"\nposition = [0, 2, 0]\nforward = [0, -1, 0]\nup = [0, 0, 1]").unwrap();
+        let camera = camera.to_camera_animation(4, 4).unwrap();
+
+        let mut sampler = create_sampler(settings, 4, 4, settings.seed);
+        let mut sum = 0f32;
+        for sample_index in 0..settings.samples
+        {
+            sampler.start_pixel_sample(2, 2, sample_index);
+            let color = parse_pixel(glm::vec2(2.5f32, 2.5f32), 0f32, settings, &camera, &scene, &material_manager, &texture_manager, &mut *sampler);
+            sum += color.x;
+        }
+
+        return sum / settings.samples as f32;
+    }
+
+    #[test]
+    fn lobe_limits_dont_darken_direct_light()
+    {
+        let mut settings = RenderSettings::default();
+        settings.sampler = SamplerType::Independent;
+        settings.samples = 20000;
+        // One bounce off the floor, without Russian roulette.
+        settings.max_depth = 2;
+        settings.min_depth = 2;
+        let reference = render_floor(&settings);
+
+        // Every bounce ends on its lobe's limit, only light sampling sees the light.
+        settings.max_diffuse_depth = 0;
+        settings.max_specular_depth = 0;
+        let limited = render_floor(&settings);
+
+        assert!(reference > 0.25f32, "{}", reference);
+        assert!((limited - reference).abs() < 0.02f32 * reference, "{} with the limits, {} without", limited, reference);
+    }
 }
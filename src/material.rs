@@ -17,6 +17,8 @@ pub struct Material
     pub metallic: f32,
     pub specular: f32,
     pub roughness: f32,
+    pub emission: glm::Vec3,
+    pub emission_strength: f32,
     pub albedo_id: Option<u32>,
     pub roughness_id: Option<u32>,
     pub metallic_id: Option<u32>,
@@ -28,6 +30,7 @@ pub struct SurfaceMaterial
     pub metallic: f32,
     pub specular: f32,
     pub roughness: f32,
+    pub emission: glm::Vec3,
     pub cs_w: f32,
 }
 
@@ -41,6 +44,8 @@ impl Default for Material
             metallic: 0f32,
             specular: 0.5f32,
             roughness: 0.5f32,
+            emission: glm::vec3(1f32, 1f32, 1f32),
+            emission_strength: 0f32,
             albedo_id: None,
             roughness_id: None,
             metallic_id: None,
@@ -48,6 +53,20 @@ impl Default for Material
     }
 }
 
+impl Material
+{
+    pub fn emitted_radiance(&self) -> glm::Vec3
+    {
+        return self.emission * self.emission_strength;
+    }
+
+    pub fn is_emissive(&self) -> bool
+    {
+        let radiance = self.emitted_radiance();
+        return radiance.x > 0f32 || radiance.y > 0f32 || radiance.z > 0f32;
+    }
+}
+
 pub struct MaterialLoader
 {
 }
@@ -59,17 +78,6 @@ impl ResourceLoader<Material> for MaterialLoader {
     }
 }
 
-pub fn load_textures_of_material(material_id: &u32, material_manager: &mut MaterialManager, texture_manager: &mut TextureManager)
-{
-    let mut material = *material_manager.get(material_id).unwrap();
-
-    material.albedo_id = Some(texture_manager.load("iron_mat/albedo.png"));
-    material.roughness_id = Some(texture_manager.load("iron_mat/roughness.png"));
-    material.metallic_id = Some(texture_manager.load("iron_mat/metallic.png"));
-
-    material_manager.set(*material_id, material);
-}
-
 pub fn get_texture_xy(hit: &Hit, image: &DynamicImage) -> glm::UVec2
 {
     let scale = 5f32;
@@ -79,28 +87,6 @@ pub fn get_texture_xy(hit: &Hit, image: &DynamicImage) -> glm::UVec2
     return glm::uvec2(x, y);
 }
 
-pub fn sample_texture(image: &DynamicImage, xy: glm::UVec2, gamma_correct: bool) -> glm::Vec3
-{
-    let pixel = image.get_pixel(xy.x, xy.y);
-
-    if gamma_correct
-    {
-        return glm::vec3((pixel[0] as f32 / 255f32).powf(2.2),
-                         (pixel[1] as f32 / 255f32).powf(2.2),
-                         (pixel[2] as f32 / 255f32).powf(2.2));
-    }
-
-    return glm::vec3((pixel[0] as f32 / 255f32),
-                       (pixel[1] as f32 / 255f32),
-                       (pixel[2] as f32 / 255f32));
-}
-
-pub fn sample_texture_1d(image: &DynamicImage, xy: glm::UVec2, gamma_correct: bool) -> f32
-{
-    let pixel = image.get_pixel(xy.x, xy.y);
-    return pixel[0] as f32 / 255f32;
-}
-
 pub fn get_surface_material(hit: &Hit, material_manager: &MaterialManager, texture_manager: &TextureManager) -> SurfaceMaterial
 {
     let material = material_manager.get(&hit.material_id).unwrap();
@@ -110,6 +96,7 @@ pub fn get_surface_material(hit: &Hit, material_manager: &MaterialManager, textu
         metallic: material.metallic,
         specular: material.specular,
         roughness: material.roughness,
+        emission: material.emitted_radiance(),
         cs_w: 0f32,
     };
 
@@ -120,36 +107,25 @@ pub fn get_surface_material(hit: &Hit, material_manager: &MaterialManager, textu
     if let Some(id) = material.albedo_id
     {
         let texture = texture_manager.get(&id).unwrap();
-        let xy = get_texture_xy(&hit, &texture);
-        surface_material.color = sample_texture(&texture, xy, true);
+        let xy = get_texture_xy(&hit, &texture.image);
+        surface_material.color = texture.texel(xy);
     }
 
     // Roughness
     if let Some(id) = material.roughness_id
     {
         let texture = texture_manager.get(&id).unwrap();
-        let xy = get_texture_xy(&hit, &texture);
-        surface_material.roughness = sample_texture_1d(&texture, xy, false);
+        let xy = get_texture_xy(&hit, &texture.image);
+        surface_material.roughness = texture.texel_1d(xy);
     }
 
     // Metallic
     if let Some(id) = material.metallic_id
     {
         let texture = texture_manager.get(&id).unwrap();
-        let xy = get_texture_xy(&hit, &texture);
-        surface_material.metallic = sample_texture_1d(&texture, xy, false);
+        let xy = get_texture_xy(&hit, &texture.image);
+        surface_material.metallic = texture.texel_1d(xy);
     }
 
     return surface_material;
 }
-
-// Some pre-made materials.
-pub mod materials {
-    use crate::material::Material;
-
-    pub static GLOSSY_WHITE:   Material = Material { color: glm::Vec3 { x: 1f32, y: 1f32, z: 1f32 }, metallic: 0.0f32, specular: 0.5f32, roughness: 0.4f32, albedo_id: None, roughness_id: None, metallic_id: None, };
-    pub static GREEN: Material = Material { color: glm::Vec3 { x: 0f32, y: 1f32, z: 0f32 }, metallic: 0f32, specular: 0.5f32, roughness: 1.0f32, albedo_id: None, roughness_id: None, metallic_id: None, };
-    pub static BLUE:  Material = Material { color: glm::Vec3 { x: 0f32, y: 0f32, z: 1f32 }, metallic: 0f32, specular: 0.5f32, roughness: 1.0f32, albedo_id: None, roughness_id: None, metallic_id: None, };
-    pub static GLOSSY_ORANGE:  Material = Material { color: glm::Vec3 { x: 0.8f32, y: 0.4f32, z: 0f32 }, metallic: 0.9f32, specular: 0.5f32, roughness: 0.1f32, albedo_id: None, roughness_id: None, metallic_id: None, };
-
-}
\ No newline at end of file
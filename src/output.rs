@@ -0,0 +1,157 @@
+extern crate exr;
+
+use exr::prelude::*;
+use serde::Deserialize;
+use crate::settings::output_extension;
+use crate::tonemap::DisplayTransform;
+
+// Sample type of EXR channels. Half is plenty for color and halves the file size.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExrPrecision
+{
+    Half,
+    Float,
+}
+
+impl std::str::FromStr for ExrPrecision
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err>
+    {
+        match s
+        {
+            "half" => Ok(ExrPrecision::Half),
+            "float" => Ok(ExrPrecision::Float),
+            _ => Err(format!("Unknown EXR precision '{}'", s)),
+        }
+    }
+}
+
+// One layer of an EXR file: an optional layer name and named channels of width * height values each,
+// rows stored bottom up like the render buffers.
+pub struct ExrLayer
+{
+    pub name: Option<String>,
+    pub precision: ExrPrecision,
+    pub channels: Vec<(String, Vec<f32>)>,
+}
+
+fn flip_rows(values: &[f32], width: usize, height: usize) -> Vec<f32>
+{
+    let mut flipped = Vec::with_capacity(values.len());
+    for y in (0..height).rev()
+    {
+        flipped.extend_from_slice(&values[y * width..(y + 1) * width]);
+    }
+
+    return flipped;
+}
+
+pub fn write_exr(path: &str, width: u32, height: u32, layers: Vec<ExrLayer>) -> std::result::Result<(), String>
+{
+    let size = Vec2(width as usize, height as usize);
+
+    let layers: Vec<Layer<AnyChannels<FlatSamples>>> = layers.into_iter().map(|layer|
+    {
+        let precision = layer.precision;
+        let channels = layer.channels.into_iter().map(|(name, values)|
+        {
+            let values = flip_rows(&values, size.0, size.1);
+            let samples = match precision
+            {
+                ExrPrecision::Half => FlatSamples::F16(values.into_iter().map(f16::from_f32).collect()),
+                ExrPrecision::Float => FlatSamples::F32(values),
+            };
+            AnyChannel::new(name.as_str(), samples)
+        }).collect();
+
+        let attributes = match &layer.name
+        {
+            Some(name) => LayerAttributes::named(name.as_str()),
+            None => LayerAttributes::default(),
+        };
+
+        Layer::new(size, attributes, Encoding::FAST_LOSSLESS, AnyChannels::sort(channels))
+    }).collect();
+
+    let image = Image::from_layers(ImageAttributes::new(IntegerBounds::from_dimensions(size)), SmallVec::from_vec(layers));
+    image.write().to_file(path).map_err(|e| format!("Failed to save '{}': {}", path, e))?;
+
+    return Ok(());
+}
+
+// Portable float map with one (grayscale) or three channels, a missing third channel is written as zero.
+// PFM rows are stored bottom up, same as the render buffers.
+pub fn write_pfm(path: &str, width: u32, height: u32, channels: &[Vec<f32>]) -> std::result::Result<(), String>
+{
+    let (magic, count) = match channels.len()
+    {
+        1 => ("Pf", 1),
+        2 | 3 => ("PF", 3),
+        n => return Err(format!("Can't save {} channels to PFM '{}'", n, path)),
+    };
+
+    // A negative scale marks little endian samples.
+    let mut data = format!("{}\n{} {}\n-1.0\n", magic, width, height).into_bytes();
+    data.reserve((width * height) as usize * count * 4);
+    for i in 0..(width * height) as usize
+    {
+        for c in 0..count
+        {
+            let value = channels.get(c).map(|channel| channel[i]).unwrap_or(0f32);
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    std::fs::write(path, &data).map_err(|e| format!("Failed to save '{}': {}", path, e))?;
+
+    return Ok(());
+}
+
+// 16 bit PNG through the display transform.
+fn write_png(path: &str, rgb: &[f32], width: u32, height: u32, display: &DisplayTransform) -> std::result::Result<(), String>
+{
+    let encode = |value: f32| (value * 65535f32).round() as u16;
+    let image = image::ImageBuffer::from_fn(width, height, |x, y|
+    {
+        let i = (((height - 1 - y) * width + x) * 3) as usize;
+        let color = display.to_display(glm::vec3(rgb[i], rgb[i + 1], rgb[i + 2]));
+        image::Rgb([encode(color.x), encode(color.y), encode(color.z)])
+    });
+
+    image.save(path).map_err(|e| format!("Failed to save '{}': {}", path, e))?;
+
+    return Ok(());
+}
+
+// Saves interleaved linear RGB in the format given by the extension. EXR and PFM keep the linear values,
+// only PNG goes through the display transform.
+pub fn save_rgb(path: &str, rgb: &[f32], width: u32, height: u32, precision: ExrPrecision, display: &DisplayTransform) -> std::result::Result<(), String>
+{
+    let channel = |c: usize| rgb.iter().skip(c).step_by(3).cloned().collect::<Vec<f32>>();
+
+    return match output_extension(path).as_str()
+    {
+        "png" => write_png(path, rgb, width, height, display),
+        "exr" =>
+        {
+            let channels = vec![("R".to_owned(), channel(0)), ("G".to_owned(), channel(1)), ("B".to_owned(), channel(2))];
+            write_exr(path, width, height, vec![ExrLayer { name: None, precision, channels }])
+        },
+        "pfm" => write_pfm(path, width, height, &[channel(0), channel(1), channel(2)]),
+        extension => Err(format!("Unsupported output format '{}'", extension)),
+    };
+}
+
+// Single channel float image, EXR or PFM.
+pub fn save_gray(path: &str, values: Vec<f32>, width: u32, height: u32) -> std::result::Result<(), String>
+{
+    return match output_extension(path).as_str()
+    {
+        "exr" => write_exr(path, width, height, vec![ExrLayer { name: None, precision: ExrPrecision::Float, channels: vec![("Y".to_owned(), values)] }]),
+        "pfm" => write_pfm(path, width, height, &[values]),
+        extension => Err(format!("Unsupported output format '{}'", extension)),
+    };
+}
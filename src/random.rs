@@ -3,10 +3,26 @@ extern crate rand;
 use rand::{Rng, SeedableRng};
 use rand::rngs::SmallRng as RandGenerator;
 
+// Seed offset for the random streams of the denoiser G-buffer pass.
+pub static GBUFFER_STREAM: u64 = 0x9e37_79b9_7f4a_7c15;
+
+// SplitMix64 finalizer.
+pub fn mix_bits(mut v: u64) -> u64
+{
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5_d329_728e_a185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81da_def4_bc2d_d44d);
+    v ^= v >> 33;
+    return v;
+}
+
+// Every (pixel, sample) pair gets its own stream, so the result doesn't depend on which thread renders what.
 #[allow(dead_code)]
-pub fn create_rand_generator() -> RandGenerator
+pub fn create_rand_generator(seed: u64, pixel_index: u32, sample_index: u32) -> RandGenerator
 {
-    return rand::rngs::SmallRng::from_entropy()
+    let key = mix_bits(seed ^ mix_bits(((pixel_index as u64) << 32) | sample_index as u64));
+    return rand::rngs::SmallRng::seed_from_u64(key);
 }
 
 #[allow(dead_code)]
@@ -31,4 +47,25 @@ pub fn next_rand(rng: &mut RandGenerator) -> f32
 pub fn next_rand_v2(rng: &mut RandGenerator) -> glm::Vec2
 {
     return glm::vec2(rng.gen_range(0f32, 1f32), rng.gen_range(0f32, 1f32));
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn stream(seed: u64, pixel_index: u32, sample_index: u32) -> Vec<f32>
+    {
+        let mut rng = create_rand_generator(seed, pixel_index, sample_index);
+        return (0..8).map(|_| next_rand(&mut rng)).collect();
+    }
+
+    #[test]
+    fn streams_only_depend_on_seed_pixel_and_sample()
+    {
+        assert_eq!(stream(3, 100, 7), stream(3, 100, 7));
+        assert_ne!(stream(3, 100, 7), stream(4, 100, 7));
+        assert_ne!(stream(3, 100, 7), stream(3, 101, 7));
+        assert_ne!(stream(3, 100, 7), stream(3, 100, 8));
+    }
+}
@@ -0,0 +1,647 @@
+use serde::Deserialize;
+use rand::rngs::SmallRng as RandGenerator;
+use crate::random::*;
+use crate::settings::RenderSettings;
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplerType
+{
+    Independent,
+    Stratified,
+    Sobol,
+    Halton,
+    // Z-order Sobol, distributes the error between neighbouring pixels as blue noise.
+    BlueNoise,
+}
+
+impl std::str::FromStr for SamplerType
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s
+        {
+            "independent" => Ok(SamplerType::Independent),
+            "stratified" => Ok(SamplerType::Stratified),
+            "sobol" => Ok(SamplerType::Sobol),
+            "halton" => Ok(SamplerType::Halton),
+            "blue_noise" => Ok(SamplerType::BlueNoise),
+            _ => Err(format!("Unknown sampler '{}'", s)),
+        }
+    }
+}
+
+// Source of the sample values of a path. Every value comes from its own dimension, so the integrator
+// should jump to a fixed dimension at each bounce to keep the same decision on the same dimension.
+pub trait Sampler
+{
+    // Starts sample `sample_index` of a pixel, the dimension is reset to 0.
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32);
+    fn set_dimension(&mut self, dimension: u32);
+    fn next_1d(&mut self) -> f32;
+    fn next_2d(&mut self) -> glm::Vec2;
+}
+
+static ONE_MINUS_EPSILON: f32 = 0.99999994;
+
+// Combines two keys into one well mixed hash.
+fn hash(a: u64, b: u64) -> u64
+{
+    return mix_bits(a ^ mix_bits(b.wrapping_add(0x9e37_79b9_7f4a_7c15)));
+}
+
+fn hash_to_float(h: u64) -> f32
+{
+    return (h >> 40) as f32 / (1u64 << 24) as f32;
+}
+
+fn u32_to_float(v: u32) -> f32
+{
+    return ((v >> 8) as f32 / (1u32 << 24) as f32).min(ONE_MINUS_EPSILON);
+}
+
+// `width` and `height` are the size of the image being rendered, which is the preview size in the window.
+pub fn create_sampler(settings: &RenderSettings, width: u32, height: u32, seed: u64) -> Box<dyn Sampler>
+{
+    return match settings.sampler
+    {
+        SamplerType::Independent => Box::new(IndependentSampler::new(seed, width)),
+        SamplerType::Stratified => Box::new(StratifiedSampler::new(seed, width, settings.samples)),
+        SamplerType::Sobol => Box::new(SobolSampler::new(seed, width)),
+        SamplerType::Halton => Box::new(HaltonSampler::new(seed, width)),
+        // The outputs are checked against the index size in `RenderSettings::validate`, only a preview in a very
+        // large window can get past it.
+        SamplerType::BlueNoise if BlueNoiseSampler::index_bits(width, height, settings.samples) > BLUE_NOISE_MAX_INDEX_BITS => Box::new(SobolSampler::new(seed, width)),
+        SamplerType::BlueNoise => Box::new(BlueNoiseSampler::new(seed, width, height, settings.samples)),
+    };
+}
+
+// Uniform random numbers, the same as before samplers existed.
+pub struct IndependentSampler
+{
+    seed: u64,
+    width: u32,
+    rng: RandGenerator,
+}
+
+impl IndependentSampler
+{
+    pub fn new(seed: u64, width: u32) -> IndependentSampler
+    {
+        IndependentSampler
+        {
+            seed,
+            width,
+            rng: create_rand_generator(seed, 0, 0),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler
+{
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32)
+    {
+        self.rng = create_rand_generator(self.seed, y * self.width + x, sample_index);
+    }
+
+    fn set_dimension(&mut self, _dimension: u32) {}
+
+    fn next_1d(&mut self) -> f32
+    {
+        return next_rand(&mut self.rng);
+    }
+
+    fn next_2d(&mut self) -> glm::Vec2
+    {
+        return next_rand_v2(&mut self.rng);
+    }
+}
+
+// Kensler's hashed permutation, returns element `i` of a random permutation of [0, l).
+fn permutation_element(mut i: u32, l: u32, p: u32) -> u32
+{
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    loop
+    {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+
+        if i < l
+        {
+            break;
+        }
+    }
+
+    return i.wrapping_add(p) % l;
+}
+
+// Jittered strata over the samples of a pixel, shuffled independently for every dimension.
+pub struct StratifiedSampler
+{
+    seed: u64,
+    width: u32,
+    samples_per_pixel: u32,
+    pixel_hash: u64,
+    sample_index: u32,
+    dimension: u32,
+}
+
+impl StratifiedSampler
+{
+    pub fn new(seed: u64, width: u32, samples_per_pixel: u32) -> StratifiedSampler
+    {
+        StratifiedSampler
+        {
+            seed,
+            width,
+            samples_per_pixel: samples_per_pixel.max(1),
+            pixel_hash: 0,
+            sample_index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn jitter(&self, axis: u64) -> f32
+    {
+        return hash_to_float(hash(hash(self.pixel_hash, self.dimension as u64), ((self.sample_index as u64) << 1) | axis));
+    }
+}
+
+impl Sampler for StratifiedSampler
+{
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32)
+    {
+        self.pixel_hash = hash(self.seed, (y * self.width + x) as u64);
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn set_dimension(&mut self, dimension: u32)
+    {
+        self.dimension = dimension;
+    }
+
+    fn next_1d(&mut self) -> f32
+    {
+        let n = self.samples_per_pixel;
+        let stratum = permutation_element(self.sample_index % n, n, hash(self.pixel_hash, self.dimension as u64) as u32);
+        let value = (stratum as f32 + self.jitter(0)) / n as f32;
+
+        self.dimension += 1;
+        return value.min(ONE_MINUS_EPSILON);
+    }
+
+    fn next_2d(&mut self) -> glm::Vec2
+    {
+        // The grid may have a few more cells than samples when the sample count isn't a square.
+        let nx = (self.samples_per_pixel as f32).sqrt().ceil() as u32;
+        let ny = (self.samples_per_pixel + nx - 1) / nx;
+        let n = nx * ny;
+
+        let stratum = permutation_element(self.sample_index % n, n, hash(self.pixel_hash, self.dimension as u64) as u32);
+        let x = ((stratum % nx) as f32 + self.jitter(0)) / nx as f32;
+        let y = ((stratum / nx) as f32 + self.jitter(1)) / ny as f32;
+
+        self.dimension += 2;
+        return glm::vec2(x.min(ONE_MINUS_EPSILON), y.min(ONE_MINUS_EPSILON));
+    }
+}
+
+// First two dimensions of the Sobol sequence, the first is the van der Corput sequence.
+fn sobol_0(index: u32) -> u32
+{
+    return index.reverse_bits();
+}
+
+fn sobol_1(mut index: u32) -> u32
+{
+    let mut result = 0u32;
+    let mut direction = 1u32 << 31;
+    while index != 0
+    {
+        if index & 1 != 0
+        {
+            result ^= direction;
+        }
+
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+
+    return result;
+}
+
+// Burley, "Practical Hash-based Owen Scrambling", 2020.
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32
+{
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    return x;
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32
+{
+    return laine_karras_permutation(x.reverse_bits(), seed).reverse_bits();
+}
+
+// Owen-scrambled Sobol, every 1D or 2D request is a padded Sobol sample with its own shuffled index.
+pub struct SobolSampler
+{
+    seed: u64,
+    width: u32,
+    pixel_hash: u64,
+    sample_index: u32,
+    dimension: u32,
+}
+
+impl SobolSampler
+{
+    pub fn new(seed: u64, width: u32) -> SobolSampler
+    {
+        SobolSampler
+        {
+            seed,
+            width,
+            pixel_hash: 0,
+            sample_index: 0,
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for SobolSampler
+{
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32)
+    {
+        self.pixel_hash = hash(self.seed, (y * self.width + x) as u64);
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn set_dimension(&mut self, dimension: u32)
+    {
+        self.dimension = dimension;
+    }
+
+    fn next_1d(&mut self) -> f32
+    {
+        let h = hash(self.pixel_hash, self.dimension as u64);
+        let index = nested_uniform_scramble(self.sample_index, h as u32);
+
+        self.dimension += 1;
+        return u32_to_float(nested_uniform_scramble(sobol_0(index), (h >> 32) as u32));
+    }
+
+    fn next_2d(&mut self) -> glm::Vec2
+    {
+        let h = hash(self.pixel_hash, self.dimension as u64);
+        let index = nested_uniform_scramble(self.sample_index, h as u32);
+        let h2 = mix_bits(h);
+
+        self.dimension += 2;
+        return glm::vec2(
+            u32_to_float(nested_uniform_scramble(sobol_0(index), (h >> 32) as u32)),
+            u32_to_float(nested_uniform_scramble(sobol_1(index), h2 as u32))
+        );
+    }
+}
+
+static PRIMES: [u32; 128] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+    137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193, 197, 199, 211, 223,
+    227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307, 311,
+    313, 317, 331, 337, 347, 349, 353, 359, 367, 373, 379, 383, 389, 397, 401, 409,
+    419, 421, 431, 433, 439, 443, 449, 457, 461, 463, 467, 479, 487, 491, 499, 503,
+    509, 521, 523, 541, 547, 557, 563, 569, 571, 577, 587, 593, 599, 601, 607, 613,
+    617, 619, 631, 641, 643, 647, 653, 659, 661, 673, 677, 683, 691, 701, 709, 719,
+];
+
+fn radical_inverse(base: u32, mut index: u32) -> f32
+{
+    let inv_base = 1f64 / base as f64;
+    let mut reversed = 0u64;
+    let mut inv_base_n = 1f64;
+    while index > 0
+    {
+        let next = index / base;
+        let digit = index - next * base;
+        reversed = reversed * base as u64 + digit as u64;
+        inv_base_n *= inv_base;
+        index = next;
+    }
+
+    return ((reversed as f64 * inv_base_n) as f32).min(ONE_MINUS_EPSILON);
+}
+
+// Halton sequence with a random per pixel Cranley-Patterson rotation. Dimensions past the prime table
+// fall back to hashed random numbers.
+pub struct HaltonSampler
+{
+    seed: u64,
+    width: u32,
+    pixel_hash: u64,
+    sample_index: u32,
+    dimension: u32,
+}
+
+impl HaltonSampler
+{
+    pub fn new(seed: u64, width: u32) -> HaltonSampler
+    {
+        HaltonSampler
+        {
+            seed,
+            width,
+            pixel_hash: 0,
+            sample_index: 0,
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for HaltonSampler
+{
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32)
+    {
+        self.pixel_hash = hash(self.seed, (y * self.width + x) as u64);
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn set_dimension(&mut self, dimension: u32)
+    {
+        self.dimension = dimension;
+    }
+
+    fn next_1d(&mut self) -> f32
+    {
+        let h = hash(self.pixel_hash, self.dimension as u64);
+        let dimension = self.dimension as usize;
+        self.dimension += 1;
+
+        if dimension >= PRIMES.len()
+        {
+            return hash_to_float(hash(h, self.sample_index as u64));
+        }
+
+        let value = radical_inverse(PRIMES[dimension], self.sample_index) + hash_to_float(h);
+        return (value - value.floor()).min(ONE_MINUS_EPSILON);
+    }
+
+    fn next_2d(&mut self) -> glm::Vec2
+    {
+        let x = self.next_1d();
+        let y = self.next_1d();
+        return glm::vec2(x, y);
+    }
+}
+
+static PERMUTATIONS_4: [[u8; 4]; 24] = [
+    [0, 1, 2, 3], [0, 1, 3, 2], [0, 2, 1, 3], [0, 2, 3, 1], [0, 3, 1, 2], [0, 3, 2, 1],
+    [1, 0, 2, 3], [1, 0, 3, 2], [1, 2, 0, 3], [1, 2, 3, 0], [1, 3, 0, 2], [1, 3, 2, 0],
+    [2, 0, 1, 3], [2, 0, 3, 1], [2, 1, 0, 3], [2, 1, 3, 0], [2, 3, 0, 1], [2, 3, 1, 0],
+    [3, 0, 1, 2], [3, 0, 2, 1], [3, 1, 0, 2], [3, 1, 2, 0], [3, 2, 0, 1], [3, 2, 1, 0],
+];
+
+fn ceil_log2(v: u32) -> u32
+{
+    return 32 - v.max(1).saturating_sub(1).leading_zeros();
+}
+
+fn left_shift_2(mut x: u64) -> u64
+{
+    x &= 0xffff_ffff;
+    x = (x ^ (x << 16)) & 0x0000_ffff_0000_ffff;
+    x = (x ^ (x << 8)) & 0x00ff_00ff_00ff_00ff;
+    x = (x ^ (x << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x ^ (x << 2)) & 0x3333_3333_3333_3333;
+    x = (x ^ (x << 1)) & 0x5555_5555_5555_5555;
+    return x;
+}
+
+fn encode_morton_2(x: u32, y: u32) -> u64
+{
+    return (left_shift_2(y as u64) << 1) | left_shift_2(x as u64);
+}
+
+// Ahmed and Wonka, "Screen-Space Blue-Noise Diffusion of Monte Carlo Sampling Error via Hierarchical
+// Ordering of Pixels", 2020. One Sobol sequence is spread over the whole image in Morton order, with
+// the base 4 digits of the index shuffled per dimension.
+pub struct BlueNoiseSampler
+{
+    seed: u64,
+    log2_samples_per_pixel: u32,
+    base4_digits: u32,
+    morton_index: u64,
+    dimension: u32,
+}
+
+// The Sobol generators are 32 bits wide, the Morton index of every pixel sample has to fit in them.
+pub static BLUE_NOISE_MAX_INDEX_BITS: u32 = 32;
+
+impl BlueNoiseSampler
+{
+    pub fn new(seed: u64, width: u32, height: u32, samples_per_pixel: u32) -> BlueNoiseSampler
+    {
+        assert!(BlueNoiseSampler::index_bits(width, height, samples_per_pixel) <= BLUE_NOISE_MAX_INDEX_BITS,
+            "{}x{} at {} samples per pixel is too large for the blue noise sampler", width, height, samples_per_pixel);
+
+        let log2_samples_per_pixel = ceil_log2(samples_per_pixel);
+        let log2_resolution = ceil_log2(width.max(height));
+
+        BlueNoiseSampler
+        {
+            seed,
+            log2_samples_per_pixel,
+            base4_digits: log2_resolution + (log2_samples_per_pixel + 1) / 2,
+            morton_index: 0,
+            dimension: 0,
+        }
+    }
+
+    // Bits of the Morton index over all pixels and samples of an image.
+    pub fn index_bits(width: u32, height: u32, samples_per_pixel: u32) -> u32
+    {
+        return 2 * ceil_log2(width.max(height)) + ceil_log2(samples_per_pixel);
+    }
+
+    fn sample_index(&self) -> u64
+    {
+        let odd_power = self.log2_samples_per_pixel & 1 == 1;
+        let last_digit = if odd_power { 1 } else { 0 };
+        let dimension_key = 0x5555_5555u64.wrapping_mul(self.dimension as u64);
+
+        let mut sample_index = 0u64;
+        for i in (last_digit..self.base4_digits).rev()
+        {
+            let digit_shift = 2 * i - last_digit;
+            let digit = (self.morton_index >> digit_shift) & 3;
+            let higher_digits = self.morton_index >> (digit_shift + 2);
+            let permutation = (mix_bits(higher_digits ^ dimension_key) >> 24) % 24;
+            sample_index |= (PERMUTATIONS_4[permutation as usize][digit as usize] as u64) << digit_shift;
+        }
+
+        if odd_power
+        {
+            let digit = self.morton_index & 1;
+            sample_index |= digit ^ (mix_bits((self.morton_index >> 1) ^ dimension_key) & 1);
+        }
+
+        return sample_index;
+    }
+}
+
+impl Sampler for BlueNoiseSampler
+{
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32)
+    {
+        // Samples past the power of two the sampler was set up for wrap around.
+        let local_index = sample_index as u64 & ((1u64 << self.log2_samples_per_pixel) - 1);
+        self.morton_index = (encode_morton_2(x, y) << self.log2_samples_per_pixel) | local_index;
+        self.dimension = 0;
+    }
+
+    fn set_dimension(&mut self, dimension: u32)
+    {
+        self.dimension = dimension;
+    }
+
+    fn next_1d(&mut self) -> f32
+    {
+        let index = self.sample_index() as u32;
+        let h = hash(self.seed, self.dimension as u64);
+
+        self.dimension += 1;
+        return u32_to_float(nested_uniform_scramble(sobol_0(index), h as u32));
+    }
+
+    fn next_2d(&mut self) -> glm::Vec2
+    {
+        let index = self.sample_index() as u32;
+        let h = hash(self.seed, self.dimension as u64);
+
+        self.dimension += 2;
+        return glm::vec2(
+            u32_to_float(nested_uniform_scramble(sobol_0(index), h as u32)),
+            u32_to_float(nested_uniform_scramble(sobol_1(index), (h >> 32) as u32))
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    static SAMPLER_TYPES: [SamplerType; 5] = [SamplerType::Independent, SamplerType::Stratified, SamplerType::Sobol, SamplerType::Halton, SamplerType::BlueNoise];
+
+    // Draws a few values of one pixel sample, jumping to a later dimension like the integrator does at each bounce.
+    fn draw(sampler: &mut dyn Sampler, x: u32, y: u32, sample_index: u32) -> Vec<f32>
+    {
+        sampler.start_pixel_sample(x, y, sample_index);
+
+        let mut values = Vec::new();
+        for &dimension in &[0, 5, 12, 200]
+        {
+            sampler.set_dimension(dimension);
+            let v = sampler.next_2d();
+            values.extend_from_slice(&[v.x, v.y, sampler.next_1d()]);
+        }
+
+        return values;
+    }
+
+    fn settings(sampler: SamplerType) -> RenderSettings
+    {
+        let mut settings = RenderSettings::default();
+        settings.sampler = sampler;
+        settings.samples = 16;
+        return settings;
+    }
+
+    #[test]
+    fn same_seed_pixel_and_sample_give_the_same_values()
+    {
+        for &sampler_type in &SAMPLER_TYPES
+        {
+            let settings = settings(sampler_type);
+            let mut a = create_sampler(&settings, 64, 32, 7);
+            let mut b = create_sampler(&settings, 64, 32, 7);
+
+            // Other pixel samples drawn first, like a thread that rendered another tile before.
+            draw(&mut *b, 3, 9, 2);
+            draw(&mut *b, 40, 20, 5);
+
+            assert_eq!(draw(&mut *a, 12, 5, 3), draw(&mut *b, 12, 5, 3), "{:?}", sampler_type);
+        }
+    }
+
+    #[test]
+    fn seed_pixel_and_sample_change_the_values()
+    {
+        for &sampler_type in &SAMPLER_TYPES
+        {
+            let settings = settings(sampler_type);
+            let mut sampler = create_sampler(&settings, 64, 32, 7);
+            let mut other_seed = create_sampler(&settings, 64, 32, 8);
+
+            let values = draw(&mut *sampler, 12, 5, 3);
+            assert_ne!(values, draw(&mut *sampler, 12, 5, 4), "{:?}", sampler_type);
+            assert_ne!(values, draw(&mut *sampler, 13, 5, 3), "{:?}", sampler_type);
+            assert_ne!(values, draw(&mut *other_seed, 12, 5, 3), "{:?}", sampler_type);
+        }
+    }
+
+    #[test]
+    fn values_are_in_the_unit_interval()
+    {
+        for &sampler_type in &SAMPLER_TYPES
+        {
+            let settings = settings(sampler_type);
+            let mut sampler = create_sampler(&settings, 16, 16, 1);
+
+            for sample_index in 0..settings.samples
+            {
+                for value in draw(&mut *sampler, 7, 11, sample_index)
+                {
+                    assert!(value >= 0f32 && value < 1f32, "{:?} gave {}", sampler_type, value);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn blue_noise_index_bits()
+    {
+        assert_eq!(BlueNoiseSampler::index_bits(1920, 1080, 64), 2 * 11 + 6);
+        assert!(BlueNoiseSampler::index_bits(4096, 2160, 1024) > BLUE_NOISE_MAX_INDEX_BITS);
+    }
+}
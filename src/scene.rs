@@ -5,53 +5,128 @@ use bvh::bvh::BVH;
 use bvh::ray::Ray;
 use crate::shape::*;
 use crate::triangle::*;
+use crate::transform::MotionTransform;
 use crate::model::Vertex;
+use std::sync::Arc;
+use crate::light::*;
+use crate::environment::Environment;
 
 pub struct Hit
 {
     pub pos: glm::Vec3,
     pub normal: glm::Vec3,
+    pub geometric_normal: glm::Vec3,
     pub tangent: glm::Vec3,
     pub bitangent: glm::Vec3,
     pub uv: glm::Vec2,
     pub material_id: u32,
+    pub primitive_id: u32,
+    // Time of the ray that found the hit, rays leaving the surface see the scene at the same time.
     pub time: f32,
 }
 
+static ONE_MINUS_EPSILON: f32 = 0.99999994f32;
+
 pub struct SceneGraph
 {
     pub triangles: Vec<Triangle>,
     pub bvh: Option<BVH>,
+    pub environment: Environment,
+    pub lights: Lights,
 }
 
 impl SceneGraph
 {
-    pub fn add_tri(&mut self, v0: Vertex, v1: Vertex, v2: Vertex, material_id: u32)
+    // A triangle with a motion has its vertices in object space.
+    pub fn add_tri(&mut self, v0: Vertex, v1: Vertex, v2: Vertex, material_id: u32, motion: Option<Arc<MotionTransform>>)
     {
         self.triangles.push(Triangle {
             v0,
             v1,
             v2,
             material_id,
+            primitive_id: self.triangles.len() as u32,
             node_index: 0usize,
+            motion,
+            motion_bounds: None,
         });
     }
 
+    pub fn add_light(&mut self, v0: Vertex, v1: Vertex, v2: Vertex, material_id: u32, motion: Option<Arc<MotionTransform>>, emission: glm::Vec3)
+    {
+        self.lights.add(self.triangles.len() as u32, emission);
+        self.add_tri(v0, v1, v2, material_id, motion);
+    }
+
     #[allow(dead_code)]
     pub fn clear(&mut self)
     {
         self.triangles.clear();
+        self.lights.clear();
+    }
+
+    pub fn is_animated(&self) -> bool
+    {
+        return self.triangles.iter().any(|triangle| triangle.motion.is_some());
     }
 
-    pub fn build(&mut self)
+    // Builds the BVH over where the triangles are while the shutter is open. A static scene can keep its BVH
+    // for every frame, an animated one is rebuilt for each frame's shutter interval.
+    pub fn build(&mut self, shutter_open: f32, shutter_close: f32)
     {
+        for triangle in &mut self.triangles
+        {
+            triangle.update_bounds(shutter_open, shutter_close);
+        }
+
         let bvh = BVH::build(&mut self.triangles);
         self.bvh = Some(bvh);
+
+        self.lights.build(&self.triangles, shutter_open);
+    }
+
+    // Probability of sampling the environment instead of the emissive triangles during next event estimation.
+    fn environment_probability(&self) -> f32
+    {
+        return match (self.environment.is_importance_sampled(), self.lights.can_sample())
+        {
+            (true, true) => 0.5f32,
+            (true, false) => 1f32,
+            (false, _) => 0f32,
+        };
+    }
+
+    pub fn sample_light(&self, pos: glm::Vec3, u_select: f32, u: glm::Vec2, time: f32) -> Option<LightSample>
+    {
+        let env_prob = self.environment_probability();
+
+        if u_select < env_prob
+        {
+            let mut sample = self.environment.sample(u)?;
+            sample.pdf *= env_prob;
+            return Some(sample);
+        }
+
+        let u_select = ((u_select - env_prob) / (1f32 - env_prob)).min(ONE_MINUS_EPSILON);
+        let mut sample = self.lights.sample(&self.triangles, pos, u_select, u, time)?;
+        sample.pdf *= 1f32 - env_prob;
+        return Some(sample);
     }
 
-    pub fn traverse(&self, origin: glm::Vec3, direction: glm::Vec3) -> Option<Hit>
+    // Solid angle pdf of `sample_light` picking a point on an emissive triangle, `light_normal` is its geometric normal.
+    pub fn light_pdf(&self, emission: glm::Vec3, pos: glm::Vec3, light_pos: glm::Vec3, light_normal: glm::Vec3) -> f32
     {
+        return self.lights.pdf(emission, pos, light_pos, light_normal) * (1f32 - self.environment_probability());
+    }
 
+    // Solid angle pdf of `sample_light` picking the environment in the given direction.
+    pub fn environment_pdf(&self, direction: glm::Vec3) -> f32
+    {
+        return self.environment.pdf(direction) * self.environment_probability();
+    }
+
+    pub fn traverse(&self, origin: glm::Vec3, direction: glm::Vec3, time: f32) -> Option<Hit>
+    {
         let nalgebra_origin = bvh::nalgebra::Point3::new(origin.x, origin.y, origin.z);
         let nalgebra_direction = bvh::nalgebra::Vector3::new(direction.x, direction.y, direction.z);
         let ray = Ray::new(nalgebra_origin, nalgebra_direction);
@@ -68,6 +143,7 @@ impl SceneGraph
 
                 for obj in &bb_hit_objects
                 {
+                    let obj = obj.at_time(time);
                     let mut barry = glm::vec2(0f32, 0f32);
                     let t = obj.intersect(origin, direction, &mut barry);
                     if let Some(t) = t
@@ -96,11 +172,13 @@ impl SceneGraph
                     {
                         pos: hit_pos,
                         normal,
+                        geometric_normal: closest_obj.get_geometric_normal(),
                         tangent,
                         bitangent,
                         uv,
                         material_id,
-                        time: closest_t,
+                        primitive_id: closest_obj.get_primitive_id(),
+                        time,
                     });
                 }
             }
@@ -114,7 +192,7 @@ impl SceneGraph
         return self.triangles.len();
     }
 
-    pub fn traverse_shadow(&self, origin: glm::Vec3, direction: glm::Vec3, max_distance: f32) -> bool
+    pub fn traverse_shadow(&self, origin: glm::Vec3, direction: glm::Vec3, max_distance: f32, time: f32) -> bool
     {
         let nalgebra_origin = bvh::nalgebra::Point3::new(origin.x, origin.y, origin.z);
         let nalgebra_direction = bvh::nalgebra::Vector3::new(direction.x, direction.y, direction.z);
@@ -129,11 +207,11 @@ impl SceneGraph
                 for obj in &bb_hit_objects
                 {
                     let mut barry = glm::vec2(0f32, 0f32);
-                    let t = obj.intersect(origin, direction, &mut barry);
+                    let t = obj.at_time(time).intersect(origin, direction, &mut barry);
 
                     if let Some(t) = t
                     {
-                        if t <= max_distance
+                        if t > 0f32 && t <= max_distance
                         {
                             return true;
                         }
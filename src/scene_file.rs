@@ -0,0 +1,602 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use crate::camera::*;
+use crate::color::ColorSpace;
+use crate::environment::Environment;
+use crate::material::*;
+use crate::model::ModelManager;
+use crate::scene::SceneGraph;
+use crate::settings::RenderSettings;
+use crate::texture::{TextureManager, TextureSource};
+use crate::transform::{MotionTransform, Transform};
+use crate::animation::*;
+use crate::checkpoint::fingerprint;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDescription
+{
+    // Name of the camera to render from, defaults to the first one.
+    #[serde(default)]
+    pub camera: Option<String>,
+    #[serde(default)]
+    pub render: RenderSettings,
+    pub cameras: Vec<CameraDescription>,
+    #[serde(default)]
+    pub environment: EnvironmentDescription,
+    #[serde(default)]
+    pub materials: Vec<MaterialDescription>,
+    #[serde(default)]
+    pub models: Vec<ModelDescription>,
+
+    // Directory of the scene file, relative asset paths are resolved against it.
+    #[serde(skip)]
+    pub base_dir: PathBuf,
+    // Hash of the file contents and of the assets it loads, a checkpoint only resumes with the same scene.
+    #[serde(skip)]
+    pub fingerprint: u64,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProjectionType
+{
+    Perspective,
+    Orthographic,
+    Fisheye,
+    Equirectangular,
+}
+
+impl Default for ProjectionType
+{
+    fn default() -> Self
+    {
+        ProjectionType::Perspective
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDescription
+{
+    pub name: String,
+    pub position: [f32; 3],
+    // Either a point to look at or a view direction.
+    #[serde(default)]
+    pub target: Option<[f32; 3]>,
+    #[serde(default)]
+    pub forward: Option<[f32; 3]>,
+    #[serde(default = "default_up")]
+    pub up: [f32; 3],
+    #[serde(default)]
+    pub projection: ProjectionType,
+    // Vertical extent of an orthographic view in scene units.
+    #[serde(default)]
+    pub ortho_height: Option<f32>,
+    // For a fisheye `fov` is the angle covered by the image circle (default 180) and hfov and focal_length don't apply.
+    // Otherwise at most one of the vertical fov, horizontal fov (degrees) or focal length (mm), fov = 45 without any.
+    #[serde(default)]
+    pub fov: Option<f32>,
+    #[serde(default)]
+    pub hfov: Option<f32>,
+    #[serde(default)]
+    pub focal_length: Option<f32>,
+    #[serde(default = "default_sensor_width")]
+    pub sensor_width: f32,
+    // At most one of a lens diameter in scene units or an f-stop, a pinhole camera without either.
+    #[serde(default)]
+    pub lens_diameter: Option<f32>,
+    #[serde(default)]
+    pub f_stop: Option<f32>,
+    // Defaults to the distance to the target, or 10 when looking along `forward`.
+    #[serde(default)]
+    pub focal_distance: Option<f32>,
+    #[serde(default)]
+    pub animation: CameraAnimationDescription,
+}
+
+// Keyframe tracks, each replaces the camera value of the same name. Used for motion blur and sequences.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraAnimationDescription
+{
+    pub interpolation: Interpolation,
+    pub position: Vec<Keyframe<[f32; 3]>>,
+    pub target: Vec<Keyframe<[f32; 3]>>,
+    pub forward: Vec<Keyframe<[f32; 3]>>,
+    pub fov: Vec<Keyframe<f32>>,
+    pub hfov: Vec<Keyframe<f32>>,
+    pub focal_length: Vec<Keyframe<f32>>,
+    pub focal_distance: Vec<Keyframe<f32>>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnvironmentDescription
+{
+    // Used when no image is given.
+    pub color: [f32; 3],
+    // Equirectangular .hdr or .exr image.
+    pub image: Option<String>,
+    pub intensity: f32,
+    // Rotation around the up axis in degrees.
+    pub rotation: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaterialDescription
+{
+    pub name: String,
+    #[serde(default = "default_color")]
+    pub color: [f32; 3],
+    #[serde(default)]
+    pub metallic: f32,
+    #[serde(default = "default_half")]
+    pub specular: f32,
+    #[serde(default = "default_half")]
+    pub roughness: f32,
+    #[serde(default = "default_color")]
+    pub emission: [f32; 3],
+    #[serde(default)]
+    pub emission_strength: f32,
+    // Colors are linear, textures are decoded from their color space.
+    #[serde(default)]
+    pub albedo_texture: Option<String>,
+    #[serde(default = "default_srgb")]
+    pub albedo_color_space: ColorSpace,
+    #[serde(default)]
+    pub roughness_texture: Option<String>,
+    #[serde(default = "default_linear")]
+    pub roughness_color_space: ColorSpace,
+    #[serde(default)]
+    pub metallic_texture: Option<String>,
+    #[serde(default = "default_linear")]
+    pub metallic_color_space: ColorSpace,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelDescription
+{
+    pub path: String,
+    // Material per mesh, meshes past the end of the list use the last entry.
+    pub materials: Vec<String>,
+    #[serde(default)]
+    pub translation: [f32; 3],
+    // Euler angles in degrees.
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    #[serde(default = "default_scale")]
+    pub scale: [f32; 3],
+    #[serde(default)]
+    pub animation: TransformAnimationDescription,
+}
+
+// Keyframe tracks, each replaces the model value of the same name.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransformAnimationDescription
+{
+    pub interpolation: Interpolation,
+    pub translation: Vec<Keyframe<[f32; 3]>>,
+    pub rotation: Vec<Keyframe<[f32; 3]>>,
+    pub scale: Vec<Keyframe<[f32; 3]>>,
+}
+
+fn default_up() -> [f32; 3] { [0f32, 1f32, 0f32] }
+fn default_sensor_width() -> f32 { DEFAULT_SENSOR_WIDTH }
+fn default_color() -> [f32; 3] { [1f32, 1f32, 1f32] }
+fn default_half() -> f32 { 0.5f32 }
+fn default_scale() -> [f32; 3] { [1f32, 1f32, 1f32] }
+fn default_srgb() -> ColorSpace { ColorSpace::Srgb }
+fn default_linear() -> ColorSpace { ColorSpace::Linear }
+
+impl Default for EnvironmentDescription
+{
+    fn default() -> Self
+    {
+        EnvironmentDescription
+        {
+            color: [0.7f32, 0.7f32, 0.7f32],
+            image: None,
+            intensity: 1f32,
+            rotation: 0f32,
+        }
+    }
+}
+
+fn to_vec3(v: [f32; 3]) -> glm::Vec3
+{
+    return glm::vec3(v[0], v[1], v[2]);
+}
+
+impl CameraDescription
+{
+    pub fn validate(&self) -> Result<(), String>
+    {
+        let error = |e: &str| Err(format!("Camera '{}': {}", self.name, e));
+        let animation = &self.animation;
+        let values = |value: Option<f32>, keys: &[Keyframe<f32>]| value.into_iter().chain(keys.iter().map(|key| key.value)).collect::<Vec<f32>>();
+
+        let has_target = self.target.is_some() || !animation.target.is_empty();
+        let has_forward = self.forward.is_some() || !animation.forward.is_empty();
+        if has_target == has_forward
+        {
+            return error("needs exactly one of target and forward");
+        }
+
+        let (fov, hfov, focal_length) = (values(self.fov, &animation.fov), values(self.hfov, &animation.hfov), values(self.focal_length, &animation.focal_length));
+        if [&fov, &hfov, &focal_length].iter().filter(|v| !v.is_empty()).count() > 1
+        {
+            return error("use at most one of fov, hfov and focal_length");
+        }
+        if self.projection == ProjectionType::Fisheye
+        {
+            if !hfov.is_empty() || !focal_length.is_empty() || !animation.fov.is_empty()
+            {
+                return error("a fisheye only takes a fixed fov");
+            }
+            if fov.iter().any(|&v| v <= 0f32 || v > 360f32)
+            {
+                return error("fisheye field of view must be between 0 and 360 degrees");
+            }
+        }
+        else if fov.iter().chain(hfov.iter()).any(|&v| v <= 0f32 || v >= 180f32)
+        {
+            return error("field of view must be between 0 and 180 degrees");
+        }
+        if self.ortho_height.map_or(false, |v| v <= 0f32)
+        {
+            return error("orthographic height must be positive");
+        }
+        if focal_length.iter().any(|&v| v <= 0f32) || self.sensor_width <= 0f32
+        {
+            return error("focal length and sensor width must be positive");
+        }
+
+        if self.lens_diameter.is_some() && self.f_stop.is_some()
+        {
+            return error("use at most one of lens_diameter and f_stop");
+        }
+        if self.lens_diameter.map_or(false, |v| v < 0f32) || self.f_stop.map_or(false, |v| v <= 0f32)
+        {
+            return error("lens diameter can't be negative and the f-stop must be positive");
+        }
+
+        // Checks the view at every key, interpolation between valid keys could still pass through a bad one.
+        let camera = self.to_camera_animation(1, 1)?;
+        let times: Vec<f32> = match &camera.view
+        {
+            ViewTrack::Target(target) => camera.position.times().chain(target.times()).collect(),
+            ViewTrack::Forward(forward) => camera.position.times().chain(forward.times()).collect(),
+        };
+        for time in times
+        {
+            let direction = match &camera.view
+            {
+                ViewTrack::Target(target) => target.sample(time) - camera.position.sample(time),
+                ViewTrack::Forward(forward) => forward.sample(time),
+            };
+            if glm::length(glm::cross(direction, camera.up)) == 0f32
+            {
+                return error("view direction can't be zero or parallel to up");
+            }
+        }
+
+        return Ok(());
+    }
+
+    pub fn to_camera_animation(&self, width: u32, height: u32) -> Result<CameraAnimation, String>
+    {
+        let animation = &self.animation;
+        let error = |e: String| format!("Camera '{}': {}", self.name, e);
+        let vec3_track = |keys: &[Keyframe<[f32; 3]>], value: [f32; 3]| track_from_keys(keys, to_vec3(value), animation.interpolation, to_vec3).map_err(error);
+        let scalar_track = |keys: &[Keyframe<f32>], value: f32| track_from_keys(keys, value, animation.interpolation, |v| v).map_err(error);
+
+        let view = if self.target.is_some() || !animation.target.is_empty()
+        {
+            ViewTrack::Target(vec3_track(&animation.target, self.target.unwrap_or(self.position))?)
+        }
+        else
+        {
+            ViewTrack::Forward(vec3_track(&animation.forward, self.forward.unwrap_or([0f32, 0f32, 1f32]))?)
+        };
+
+        // A fisheye's fov is its own, it keeps the default perspective field of view.
+        let fov = if self.projection == ProjectionType::Fisheye
+        {
+            FieldOfViewTrack::Vertical(Track::constant(45f32))
+        }
+        else if self.hfov.is_some() || !animation.hfov.is_empty()
+        {
+            FieldOfViewTrack::Horizontal(scalar_track(&animation.hfov, self.hfov.unwrap_or(45f32))?)
+        }
+        else if self.focal_length.is_some() || !animation.focal_length.is_empty()
+        {
+            let focal_length = scalar_track(&animation.focal_length, self.focal_length.unwrap_or(50f32))?;
+            FieldOfViewTrack::FocalLength { focal_length, sensor_width: self.sensor_width }
+        }
+        else
+        {
+            FieldOfViewTrack::Vertical(scalar_track(&animation.fov, self.fov.unwrap_or(45f32))?)
+        };
+
+        let focal_distance = if self.focal_distance.is_some() || !animation.focal_distance.is_empty()
+        {
+            Some(scalar_track(&animation.focal_distance, self.focal_distance.unwrap_or(10f32))?)
+        }
+        else
+        {
+            None
+        };
+
+        let projection = match self.projection
+        {
+            ProjectionType::Perspective => Projection::Perspective,
+            ProjectionType::Orthographic => Projection::Orthographic { height: self.ortho_height.unwrap_or(10f32) },
+            ProjectionType::Fisheye => Projection::Fisheye { fov: self.fov.unwrap_or(180f32).to_radians() },
+            ProjectionType::Equirectangular => Projection::Equirectangular,
+        };
+
+        let aperture = match (self.lens_diameter, self.f_stop)
+        {
+            (_, Some(f_stop)) => Aperture::FStop(f_stop),
+            (Some(diameter), _) => Aperture::Diameter(diameter),
+            (None, None) => Aperture::Pinhole,
+        };
+
+        Ok(CameraAnimation
+        {
+            position: vec3_track(&animation.position, self.position)?,
+            view,
+            up: to_vec3(self.up),
+            fov,
+            sensor_width: self.sensor_width,
+            focal_distance,
+            aperture,
+            projection,
+            width,
+            height,
+        })
+    }
+}
+
+impl ModelDescription
+{
+    pub fn validate(&self) -> Result<(), String>
+    {
+        // A zero scale has no inverse for the normals, and keys of opposite sign scale through zero in between.
+        let scales: Vec<[f32; 3]> = std::iter::once(self.scale).chain(self.animation.scale.iter().map(|key| key.value)).collect();
+        if scales.iter().any(|scale| scale.iter().any(|&v| v == 0f32))
+        {
+            return Err(format!("Model '{}': scale can't be zero", self.path));
+        }
+        if self.animation.scale.windows(2).any(|pair| (0..3).any(|i| (pair[0].value[i] < 0f32) != (pair[1].value[i] < 0f32)))
+        {
+            return Err(format!("Model '{}': scale keyframes can't change sign", self.path));
+        }
+
+        return Ok(());
+    }
+}
+
+impl SceneDescription
+{
+    pub fn active_camera(&self) -> Result<&CameraDescription, String>
+    {
+        match &self.camera
+        {
+            Some(name) => self.cameras.iter().find(|c| &c.name == name).ok_or(format!("Unknown camera '{}'", name)),
+            None => self.cameras.first().ok_or("Scene has no cameras".to_owned()),
+        }
+    }
+
+    fn resolve(&self, path: &str) -> String
+    {
+        return self.base_dir.join(path).to_string_lossy().into_owned();
+    }
+
+    // Models, textures and the environment image, resolved against the scene directory.
+    fn asset_paths(&self) -> Vec<String>
+    {
+        let mut paths: Vec<&String> = self.models.iter().map(|model| &model.path).collect();
+        for material in &self.materials
+        {
+            paths.extend(material.albedo_texture.iter().chain(&material.roughness_texture).chain(&material.metallic_texture));
+        }
+        paths.extend(&self.environment.image);
+
+        return paths.iter().map(|path| self.resolve(path)).collect();
+    }
+}
+
+pub fn load_scene_description(path: &str) -> Result<SceneDescription, String>
+{
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read scene '{}': {}", path, e))?;
+    let mut description: SceneDescription = toml::from_str(&text).map_err(|e| format!("Failed to parse scene '{}': {}", path, e))?;
+
+    description.base_dir = Path::new(path).parent().map(|p| p.to_path_buf()).unwrap_or_default();
+
+    // Assets count by their contents, not their path or modification time, so renders on machines with their
+    // own copy of the scene still match. A missing one is left for `build_scene` to report.
+    let mut key = text.clone();
+    for asset in description.asset_paths()
+    {
+        let hash = std::fs::read(&asset).map(|data| fingerprint(&data)).ok();
+        key += &format!("\n{:x?}", hash);
+    }
+    description.fingerprint = fingerprint(key.as_bytes());
+
+    description.active_camera()?;
+    for camera in &description.cameras
+    {
+        camera.validate()?;
+    }
+    for model in &description.models
+    {
+        model.validate()?;
+    }
+
+    return Ok(description);
+}
+
+fn load_texture(description: &SceneDescription, path: &Option<String>, color_space: ColorSpace, texture_manager: &mut TextureManager) -> Result<Option<u32>, String>
+{
+    if let Some(path) = path
+    {
+        let id = texture_manager.load(&TextureSource { path: description.resolve(path), color_space });
+        if texture_manager.get(&id).is_none()
+        {
+            return Err(format!("Failed to load texture '{}'", path));
+        }
+
+        return Ok(Some(id));
+    }
+
+    return Ok(None);
+}
+
+// Fills the scene graph and resource managers. The BVH is not built here.
+pub fn build_scene(description: &SceneDescription, scene: &mut SceneGraph, material_manager: &mut MaterialManager, texture_manager: &mut TextureManager, model_manager: &mut ModelManager) -> Result<(), String>
+{
+    let mut environment = Environment::constant(to_vec3(description.environment.color));
+    environment.intensity = description.environment.intensity;
+    environment.rotation = description.environment.rotation.to_radians();
+    if let Some(path) = &description.environment.image
+    {
+        environment.load_image(&description.resolve(path))?;
+    }
+    scene.environment = environment;
+
+    let mut material_ids = HashMap::new();
+    for desc in &description.materials
+    {
+        let material = Material
+        {
+            color: to_vec3(desc.color),
+            metallic: desc.metallic,
+            specular: desc.specular,
+            roughness: desc.roughness,
+            emission: to_vec3(desc.emission),
+            emission_strength: desc.emission_strength,
+            albedo_id: load_texture(description, &desc.albedo_texture, desc.albedo_color_space, texture_manager)?,
+            roughness_id: load_texture(description, &desc.roughness_texture, desc.roughness_color_space, texture_manager)?,
+            metallic_id: load_texture(description, &desc.metallic_texture, desc.metallic_color_space, texture_manager)?,
+        };
+
+        material_ids.insert(desc.name.as_str(), material_manager.load(&material));
+    }
+
+    for desc in &description.models
+    {
+        if desc.materials.is_empty()
+        {
+            return Err(format!("Model '{}' has no materials", desc.path));
+        }
+
+        let mut mesh_materials = Vec::new();
+        for name in &desc.materials
+        {
+            let id = material_ids.get(name.as_str()).ok_or(format!("Model '{}' uses unknown material '{}'", desc.path, name))?;
+            mesh_materials.push(*id);
+        }
+
+        let model_handle = model_manager.load(description.resolve(&desc.path).as_str());
+        let model = model_manager.get(&model_handle).ok_or(format!("Failed to load model '{}'", desc.path))?;
+
+        let animation = &desc.animation;
+        let track = |keys: &[Keyframe<[f32; 3]>], value: [f32; 3]| track_from_keys(keys, to_vec3(value), animation.interpolation, to_vec3)
+            .map_err(|e| format!("Model '{}': {}", desc.path, e));
+        let motion = MotionTransform
+        {
+            translation: track(&animation.translation, desc.translation)?,
+            rotation: track(&animation.rotation, desc.rotation)?,
+            scale: track(&animation.scale, desc.scale)?,
+        };
+
+        let mirrored = motion.is_mirrored();
+
+        // A fixed transform is baked into the vertices, only animated models keep their motion.
+        let (transform, motion) = if motion.is_animated()
+        {
+            (None, Some(Arc::new(motion)))
+        }
+        else
+        {
+            (Some(motion.at(0f32)), None)
+        };
+
+        for (mesh_idx, mesh) in model.meshes.iter().enumerate()
+        {
+            let material_id = mesh_materials[mesh_idx.min(mesh_materials.len() - 1)];
+            let material = material_manager.get(&material_id).unwrap();
+            let vertex = |i: usize|
+            {
+                let v = &mesh.vertices[mesh.indices[i] as usize];
+                transform.map_or(*v, |transform: Transform| transform.vertex(v))
+            };
+
+            for i in (0..mesh.indices.len()).step_by(3)
+            {
+                // Only front faces are hit, so a mirrored model is wound the other way to keep them outside.
+                let (v0, v1, v2) = if mirrored { (vertex(i + 0), vertex(i + 2), vertex(i + 1)) } else { (vertex(i + 0), vertex(i + 1), vertex(i + 2)) };
+
+                if material.is_emissive()
+                {
+                    scene.add_light(v0, v1, v2, material_id, motion.clone(), material.emitted_radiance());
+                }
+                else
+                {
+                    scene.add_tri(v0, v1, v2, material_id, motion.clone());
+                }
+            }
+        }
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::test_util::temp_path;
+
+    static SCENE: &str = "\
+[[cameras]]
+name = \"main\"
+position = [0.0, 0.0, 5.0]
+target = [0.0, 0.0, 0.0]
+
+[[materials]]
+name = \"textured\"
+albedo_texture = \"albedo.png\"
+";
+
+    // Writes the scene with the given texture file into its own directory and returns its fingerprint.
+    fn scene_fingerprint(name: &str, texture: &[u8]) -> u64
+    {
+        let dir = temp_path(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        let scene_path = Path::new(&dir).join("scene.toml");
+        std::fs::write(&scene_path, SCENE).unwrap();
+        std::fs::write(Path::new(&dir).join("albedo.png"), texture).unwrap();
+
+        let description = load_scene_description(&scene_path.to_string_lossy()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        return description.fingerprint;
+    }
+
+    #[test]
+    fn fingerprint_covers_the_asset_contents()
+    {
+        let original = scene_fingerprint("scene_a", b"texture");
+
+        assert_eq!(scene_fingerprint("scene_copy", b"texture"), original);
+        assert_ne!(scene_fingerprint("scene_b", b"another texture"), original);
+    }
+}
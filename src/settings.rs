@@ -0,0 +1,437 @@
+use serde::Deserialize;
+use crate::sampler::{BlueNoiseSampler, SamplerType, BLUE_NOISE_MAX_INDEX_BITS};
+use crate::tile::TileOrder;
+use crate::aov::Aov;
+use crate::output::ExrPrecision;
+use crate::tonemap::{DisplayTransform, ToneMapper};
+use crate::filter::{Filter, FilterType};
+use crate::camera::CameraAnimation;
+use crate::checkpoint::fingerprint;
+use crate::scene_file::SceneDescription;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderSettings
+{
+    pub width: u32,
+    pub height: u32,
+    // Fraction of the window size the preview renders at while framing a shot. The outputs are always
+    // rendered at the full resolution.
+    pub resolution_scale: f32,
+    pub samples: u32,
+    pub max_depth: u32,
+    // Russian roulette starts after this many bounces.
+    pub min_depth: u32,
+    pub max_diffuse_depth: u32,
+    pub max_specular_depth: u32,
+    pub threads: u32,
+    pub jitter_aa: bool,
+    // Reconstruction filter the samples are splatted with, the radius (in pixels) defaults to the filter's own.
+    pub filter: FilterType,
+    pub filter_radius: Option<f32>,
+    // Frames start..=end are rendered, frame N at time N / frame_rate. Outputs of a sequence get the frame
+    // number in place of a run of '#' in their path, or appended to the file name.
+    pub frame_start: u32,
+    pub frame_end: Option<u32>,
+    pub frame_rate: f32,
+    // Camera rays get a time in this interval after the start of the frame, in seconds, for motion blur.
+    pub shutter_open: f32,
+    pub shutter_close: f32,
+    pub denoise: bool,
+    pub extended_denoising: bool,
+    pub gbuffer_samples: u32,
+    // Format is taken from the extension: png, or exr and pfm for linear float output.
+    pub output: String,
+    pub exr_precision: ExrPrecision,
+    // Display transform of the preview and PNG output, EXR and PFM stay linear.
+    pub tone_mapper: ToneMapper,
+    // In stops.
+    pub exposure: f32,
+    // Radiance that maps to white with the Reinhard tone mapper.
+    pub white_point: f32,
+    pub seed: u64,
+    pub sampler: SamplerType,
+    // Edge length of the square buckets handed to the render threads.
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
+    pub samples_per_pass: u32,
+    // Stop sampling pixels whose relative error is below `adaptive_threshold`, `samples` becomes the maximum.
+    pub adaptive_sampling: bool,
+    pub adaptive_threshold: f32,
+    pub adaptive_min_samples: u32,
+    // Optional grayscale image of the samples taken per pixel, EXR and PFM hold the plain counts.
+    pub sample_count_output: Option<String>,
+    // Wall clock budget of the render in seconds, 0 disables it.
+    pub time_limit: f32,
+    // Stop once the mean relative error of the image is below this, 0 disables it.
+    pub noise_threshold: f32,
+    // Periodically saves the film here so the render can be resumed.
+    pub checkpoint: Option<String>,
+    // Seconds between checkpoints.
+    pub checkpoint_interval: f32,
+    // Unnormalized film that can be merged with renders using other seeds.
+    pub film_output: Option<String>,
+    // Extra per pixel outputs, rendered with `gbuffer_samples` samples after the main render.
+    pub aovs: Vec<Aov>,
+    pub aov_output: String,
+    // All AOVs as layers of one EXR, otherwise one file per AOV with its name appended to `aov_output`.
+    pub aov_multilayer: bool,
+}
+
+impl Default for RenderSettings
+{
+    fn default() -> Self
+    {
+        RenderSettings
+        {
+            width: 600,
+            height: 600,
+            resolution_scale: 1f32,
+            samples: 100,
+            max_depth: 16,
+            min_depth: 3,
+            max_diffuse_depth: 8,
+            max_specular_depth: 16,
+            threads: 8,
+            jitter_aa: true,
+            filter: FilterType::Box,
+            filter_radius: None,
+            frame_start: 0,
+            frame_end: None,
+            frame_rate: 24f32,
+            shutter_open: 0f32,
+            shutter_close: 0f32,
+            denoise: true,
+            extended_denoising: true,
+            gbuffer_samples: 300,
+            output: "image.png".to_owned(),
+            exr_precision: ExrPrecision::Half,
+            tone_mapper: ToneMapper::Linear,
+            exposure: 0f32,
+            white_point: 4f32,
+            seed: 0,
+            sampler: SamplerType::Sobol,
+            tile_size: 32,
+            tile_order: TileOrder::Spiral,
+            samples_per_pass: 4,
+            adaptive_sampling: false,
+            adaptive_threshold: 0.01,
+            adaptive_min_samples: 16,
+            sample_count_output: None,
+            time_limit: 0f32,
+            noise_threshold: 0f32,
+            checkpoint: None,
+            checkpoint_interval: 300f32,
+            film_output: None,
+            aovs: Vec::new(),
+            aov_output: "aovs.exr".to_owned(),
+            aov_multilayer: true,
+        }
+    }
+}
+
+pub static OUTPUT_FORMATS: [&str; 3] = ["png", "exr", "pfm"];
+pub static FILM_EXTENSION: &str = "film";
+pub static AOV_FORMATS: [&str; 2] = ["exr", "pfm"];
+
+impl RenderSettings
+{
+    pub fn validate(&self) -> Result<(), String>
+    {
+        if self.width == 0 || self.height == 0
+        {
+            return Err(format!("Invalid resolution {}x{}", self.width, self.height));
+        }
+        if !(self.resolution_scale > 0f32 && self.resolution_scale <= 1f32)
+        {
+            return Err("Resolution scale must be between 0 and 1".to_owned());
+        }
+        if self.samples == 0
+        {
+            return Err("Sample count must be at least 1".to_owned());
+        }
+        if self.max_depth == 0
+        {
+            return Err("Max depth must be at least 1".to_owned());
+        }
+        if self.threads == 0
+        {
+            return Err("Thread count must be at least 1".to_owned());
+        }
+        if self.tile_size == 0
+        {
+            return Err("Tile size must be at least 1".to_owned());
+        }
+        if self.samples_per_pass == 0
+        {
+            return Err("Samples per pass must be at least 1".to_owned());
+        }
+        if !is_positive(self.adaptive_threshold)
+        {
+            return Err("Adaptive threshold must be positive".to_owned());
+        }
+        if !is_non_negative(self.time_limit) || !is_non_negative(self.noise_threshold)
+        {
+            return Err("Time limit and noise threshold can't be negative".to_owned());
+        }
+        if !is_positive(self.checkpoint_interval)
+        {
+            return Err("Checkpoint interval must be positive".to_owned());
+        }
+
+        if self.sampler == SamplerType::BlueNoise && BlueNoiseSampler::index_bits(self.width, self.height, self.samples) > BLUE_NOISE_MAX_INDEX_BITS
+        {
+            return Err(format!("Blue noise sampler supports at most 2^{} samples per image, {}x{} at {} samples per pixel is too many",
+                BLUE_NOISE_MAX_INDEX_BITS, self.width, self.height, self.samples));
+        }
+
+        let radius = self.filter().radius;
+        if !(radius >= 0.5f32 && radius.is_finite())
+        {
+            return Err("Filter radius must be at least half a pixel".to_owned());
+        }
+        if self.frame_end.map_or(false, |end| end < self.frame_start)
+        {
+            return Err(format!("Frame range {}..{} ends before it starts", self.frame_start, self.frame_end.unwrap()));
+        }
+        if !is_positive(self.frame_rate)
+        {
+            return Err("Frame rate must be positive".to_owned());
+        }
+        if !self.shutter_open.is_finite() || !self.shutter_close.is_finite()
+        {
+            return Err("Shutter times must be finite".to_owned());
+        }
+        if self.shutter_close < self.shutter_open
+        {
+            return Err("Shutter can't close before it opens".to_owned());
+        }
+        if !self.exposure.is_finite()
+        {
+            return Err("Exposure must be finite".to_owned());
+        }
+        if !is_positive(self.white_point)
+        {
+            return Err("White point must be positive".to_owned());
+        }
+
+        let extension = output_extension(&self.output);
+        if !OUTPUT_FORMATS.contains(&extension.as_str())
+        {
+            return Err(format!("Unsupported output format '{}' (supported: {})", extension, OUTPUT_FORMATS.join(", ")));
+        }
+
+        if let Some(path) = &self.sample_count_output
+        {
+            let extension = output_extension(path);
+            if !OUTPUT_FORMATS.contains(&extension.as_str())
+            {
+                return Err(format!("Unsupported sample count format '{}' (supported: {})", extension, OUTPUT_FORMATS.join(", ")));
+            }
+        }
+
+        let extension = output_extension(&self.aov_output);
+        if !self.aovs.is_empty() && !AOV_FORMATS.contains(&extension.as_str())
+        {
+            return Err(format!("Unsupported AOV format '{}' (supported: {})", extension, AOV_FORMATS.join(", ")));
+        }
+
+        return Ok(());
+    }
+
+    // Identifies the scene, camera and settings that decide what a sample sees, films only merge when they match.
+    // Seed and sample counts are left out since merged renders differ in those. The camera is taken at both ends
+    // of the shutter, so a pose moved in the preview counts as well.
+    pub fn film_fingerprint(&self, scene: &SceneDescription, camera: &CameraAnimation) -> u64
+    {
+        let filter = self.filter();
+        let key = format!("{:x} {:?} {:?} {}x{} {} {} {} {} {} {:?} {} {} {} {} {}",
+            scene.fingerprint, camera.at(self.shutter_time(0f32)), camera.at(self.shutter_time(1f32)), self.width, self.height, self.max_depth, self.min_depth, self.max_diffuse_depth,
+            self.max_specular_depth, self.jitter_aa, filter.filter_type, filter.radius, self.frame_time(), self.shutter_open, self.shutter_close, env!("CARGO_PKG_VERSION"));
+
+        return fingerprint(key.as_bytes());
+    }
+
+    // The film fingerprint plus the settings that decide which samples are taken, a checkpoint only resumes
+    // under the same ones. Outputs, thread count and stop conditions may change between runs.
+    pub fn render_fingerprint(&self, scene: &SceneDescription, camera: &CameraAnimation) -> u64
+    {
+        let key = format!("{:x} {} {} {:?} {} {} {} {}",
+            self.film_fingerprint(scene, camera), self.samples, self.seed, self.sampler,
+            self.samples_per_pass, self.adaptive_sampling, self.adaptive_threshold, self.adaptive_min_samples);
+
+        return fingerprint(key.as_bytes());
+    }
+
+    pub fn filter(&self) -> Filter
+    {
+        return Filter { filter_type: self.filter, radius: self.filter_radius.unwrap_or(self.filter.default_radius()) };
+    }
+
+    // Back buffer size of the preview in a window of the given size.
+    pub fn preview_size(&self, window_width: u32, window_height: u32) -> (u32, u32)
+    {
+        let scale = |size: u32| ((size as f32 * self.resolution_scale).round() as u32).max(1);
+        return (scale(window_width), scale(window_height));
+    }
+
+    pub fn frame_end(&self) -> u32
+    {
+        return self.frame_end.unwrap_or(self.frame_start);
+    }
+
+    pub fn is_sequence(&self) -> bool
+    {
+        return self.frame_end() > self.frame_start;
+    }
+
+    // Start of the first frame in seconds.
+    pub fn frame_time(&self) -> f32
+    {
+        return self.frame_start as f32 / self.frame_rate;
+    }
+
+    // Time of a camera ray for a uniform sample `u`.
+    pub fn shutter_time(&self, u: f32) -> f32
+    {
+        return self.frame_time() + self.shutter_open + (self.shutter_close - self.shutter_open) * u;
+    }
+
+    // Settings of a single frame of the sequence, with the frame number in the output paths.
+    pub fn for_frame(&self, frame: u32) -> RenderSettings
+    {
+        let mut settings = self.clone();
+        settings.frame_start = frame;
+        settings.frame_end = None;
+
+        if self.is_sequence()
+        {
+            let number = |path: &String| frame_path(path, frame);
+            settings.output = number(&self.output);
+            settings.sample_count_output = self.sample_count_output.as_ref().map(number);
+            settings.checkpoint = self.checkpoint.as_ref().map(number);
+            settings.film_output = self.film_output.as_ref().map(number);
+            settings.aov_output = number(&self.aov_output);
+        }
+
+        return settings;
+    }
+
+    pub fn display_transform(&self) -> DisplayTransform
+    {
+        return DisplayTransform { tone_mapper: self.tone_mapper, exposure: self.exposure, white_point: self.white_point };
+    }
+
+    // Path of the denoised image, `image.png` becomes `image_denoised.png`.
+    pub fn denoised_output(&self) -> String
+    {
+        return suffixed_path(&self.output, "_denoised");
+    }
+}
+
+// Range checks for parsed numbers, written so NaN and infinity fail them as well.
+pub fn is_positive(value: f32) -> bool
+{
+    return value > 0f32 && value.is_finite();
+}
+
+pub fn is_non_negative(value: f32) -> bool
+{
+    return value >= 0f32 && value.is_finite();
+}
+
+pub fn output_extension(path: &str) -> String
+{
+    return std::path::Path::new(path).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+}
+
+// Replaces the last run of '#' in the file name with the zero padded frame number, or appends it as `_0001`.
+pub fn frame_path(path: &str, frame: u32) -> String
+{
+    let file_name = std::path::Path::new(path).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    if let Some(end) = file_name.rfind('#')
+    {
+        let start = file_name[..end].rfind(|c| c != '#').map_or(0, |i| i + 1);
+        let width = end + 1 - start;
+        let numbered = format!("{}{:0width$}{}", &file_name[..start], frame, &file_name[end + 1..], width = width);
+        return std::path::Path::new(path).with_file_name(numbered).to_string_lossy().into_owned();
+    }
+
+    return suffixed_path(path, &format!("_{:04}", frame));
+}
+
+pub fn suffixed_path(path: &str, suffix: &str) -> String
+{
+    let path = std::path::Path::new(path);
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut file_name = format!("{}{}", stem, suffix);
+    if let Some(extension) = path.extension()
+    {
+        file_name = format!("{}.{}", file_name, extension.to_string_lossy());
+    }
+
+    return path.with_file_name(file_name).to_string_lossy().into_owned();
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn frame_path_replaces_the_last_run_of_hashes()
+    {
+        assert_eq!(frame_path("frames/image_####.png", 7), "frames/image_0007.png");
+        assert_eq!(frame_path("take#2_##.exr", 3), "take#2_03.exr");
+        assert_eq!(frame_path("image_#.png", 123), "image_123.png");
+    }
+
+    #[test]
+    fn frame_path_appends_the_number_without_hashes()
+    {
+        assert_eq!(frame_path("out/image.png", 12), "out/image_0012.png");
+        assert_eq!(frame_path("render", 5), "render_0005");
+    }
+
+    #[test]
+    fn resolution_scale_only_applies_to_the_preview()
+    {
+        let mut settings = RenderSettings::default();
+        settings.resolution_scale = 0.5;
+
+        assert_eq!(settings.preview_size(800, 601), (400, 301));
+        assert_eq!(settings.preview_size(1, 1), (1, 1));
+        assert!(settings.validate().is_ok());
+
+        settings.resolution_scale = 2f32;
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_nan_and_infinity()
+    {
+        let setters: [fn(&mut RenderSettings, f32); 11] = [
+            |s, v| s.resolution_scale = v,
+            |s, v| s.adaptive_threshold = v,
+            |s, v| s.time_limit = v,
+            |s, v| s.noise_threshold = v,
+            |s, v| s.checkpoint_interval = v,
+            |s, v| s.filter_radius = Some(v),
+            |s, v| s.frame_rate = v,
+            |s, v| s.shutter_open = v,
+            |s, v| s.shutter_close = v,
+            |s, v| s.exposure = v,
+            |s, v| s.white_point = v,
+        ];
+
+        for (i, set) in setters.iter().enumerate()
+        {
+            for &value in &[f32::NAN, f32::INFINITY]
+            {
+                let mut settings = RenderSettings::default();
+                set(&mut settings, value);
+                assert!(settings.validate().is_err(), "setting {} accepted {}", i, value);
+            }
+        }
+    }
+}
@@ -0,0 +1,7 @@
+// Helpers shared by the unit tests.
+
+// A file name in the temp directory that other test runs don't write to.
+pub fn temp_path(name: &str) -> String
+{
+    return std::env::temp_dir().join(format!("rust_gl_{}_{}", std::process::id(), name)).to_string_lossy().into_owned();
+}
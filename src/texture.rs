@@ -1,18 +1,51 @@
 extern crate image;
 
+use image::{DynamicImage, GenericImageView};
 use crate::resource_manager::*;
+use crate::color::ColorSpace;
+
+// An 8 bit image tagged with the color space its values are stored in.
+pub struct Texture
+{
+    pub image: DynamicImage,
+    pub color_space: ColorSpace,
+}
 
-pub type Texture = image::DynamicImage;
 pub type TextureManager = ResourceManager<Texture, TextureLoader>;
 
+pub struct TextureSource
+{
+    pub path: String,
+    pub color_space: ColorSpace,
+}
+
+impl Texture
+{
+    // Linear value of the texel.
+    pub fn texel(&self, xy: glm::UVec2) -> glm::Vec3
+    {
+        let pixel = self.image.get_pixel(xy.x, xy.y);
+        let decode = |value: u8| self.color_space.to_linear(value as f32 / 255f32);
+
+        return glm::vec3(decode(pixel[0]), decode(pixel[1]), decode(pixel[2]));
+    }
+
+    // Linear value of the texel's first channel, for grayscale data.
+    pub fn texel_1d(&self, xy: glm::UVec2) -> f32
+    {
+        let pixel = self.image.get_pixel(xy.x, xy.y);
+        return self.color_space.to_linear(pixel[0] as f32 / 255f32);
+    }
+}
+
 pub struct TextureLoader
 {
 }
 
 impl ResourceLoader<Texture> for TextureLoader {
-    type Args = str;
-    fn load(&self, path: &str) -> Result<Texture, String> {
-        let img = image::open(path).unwrap();
-        return Ok(img);
+    type Args = TextureSource;
+    fn load(&self, source: &TextureSource) -> Result<Texture, String> {
+        let image = image::open(&source.path).map_err(|e| format!("{}: {}", source.path, e))?;
+        return Ok(Texture { image, color_space: source.color_space });
     }
-}
\ No newline at end of file
+}
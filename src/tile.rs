@@ -0,0 +1,176 @@
+use serde::Deserialize;
+use std::sync::Mutex;
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TileOrder
+{
+    Scanline,
+    // Outwards from the center of the image.
+    Spiral,
+    Hilbert,
+}
+
+impl std::str::FromStr for TileOrder
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s
+        {
+            "scanline" => Ok(TileOrder::Scanline),
+            "spiral" => Ok(TileOrder::Spiral),
+            "hilbert" => Ok(TileOrder::Hilbert),
+            _ => Err(format!("Unknown tile order '{}'", s)),
+        }
+    }
+}
+
+// Pixel bounds of a tile, the end is exclusive.
+#[derive(Copy, Clone, Debug)]
+pub struct Tile
+{
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+// A tile together with mutable access to its rows of a buffer. Tiles of the same buffer never overlap.
+pub struct TileView<'a, T>
+{
+    pub tile: Tile,
+    rows: Vec<&'a mut [T]>,
+}
+
+impl<'a, T> TileView<'a, T>
+{
+    // `x` and `y` are image coordinates.
+    pub fn get_mut(&mut self, x: u32, y: u32) -> &mut T
+    {
+        return &mut self.rows[(y - self.tile.y0) as usize][(x - self.tile.x0) as usize];
+    }
+}
+
+// Hilbert curve index to position on an n x n grid, n a power of two.
+fn hilbert_d2xy(n: u32, d: u32) -> (u32, u32)
+{
+    let mut x = 0u32;
+    let mut y = 0u32;
+    let mut t = d;
+    let mut s = 1u32;
+    while s < n
+    {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        if ry == 0
+        {
+            if rx == 1
+            {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+
+    return (x, y);
+}
+
+// Tile grid coordinates in the order they are handed out.
+fn tile_order(tiles_x: u32, tiles_y: u32, order: TileOrder) -> Vec<(u32, u32)>
+{
+    match order
+    {
+        // Rows are stored bottom up, so start with the last row to fill the image from the top.
+        TileOrder::Scanline => (0..tiles_y).rev().flat_map(|ty| (0..tiles_x).map(move |tx| (tx, ty))).collect(),
+        TileOrder::Spiral =>
+        {
+            let center_x = (tiles_x as f32 - 1f32) * 0.5f32;
+            let center_y = (tiles_y as f32 - 1f32) * 0.5f32;
+            let mut tiles: Vec<(u32, u32)> = (0..tiles_y).flat_map(|ty| (0..tiles_x).map(move |tx| (tx, ty))).collect();
+
+            // Rings around the center, each ring walked by angle.
+            let key = |&(tx, ty): &(u32, u32)|
+            {
+                let dx = tx as f32 - center_x;
+                let dy = ty as f32 - center_y;
+                (dx.abs().max(dy.abs()), dy.atan2(dx))
+            };
+            tiles.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+            tiles
+        },
+        TileOrder::Hilbert =>
+        {
+            let n = tiles_x.max(tiles_y).next_power_of_two();
+            (0..n * n).map(|d| hilbert_d2xy(n, d)).filter(|&(tx, ty)| tx < tiles_x && ty < tiles_y).collect()
+        },
+    }
+}
+
+// Splits a row major buffer into tiles of `tile_size` pixels in the given order.
+pub fn split_into_tiles<'a, T>(buffer: &'a mut [T], width: u32, height: u32, tile_size: u32, order: TileOrder) -> Vec<TileView<'a, T>>
+{
+    let tiles_x = (width + tile_size - 1) / tile_size;
+    let tiles_y = (height + tile_size - 1) / tile_size;
+
+    let mut grid: Vec<Option<TileView<'a, T>>> = (0..tiles_x * tiles_y).map(|i|
+    {
+        let (tx, ty) = (i % tiles_x, i / tiles_x);
+        let tile = Tile
+        {
+            x0: tx * tile_size,
+            y0: ty * tile_size,
+            x1: ((tx + 1) * tile_size).min(width),
+            y1: ((ty + 1) * tile_size).min(height),
+        };
+
+        Some(TileView { tile, rows: Vec::with_capacity(tile_size as usize) })
+    }).collect();
+
+    for (y, row) in buffer.chunks_mut(width as usize).enumerate()
+    {
+        let ty = y as u32 / tile_size;
+        for (tx, piece) in row.chunks_mut(tile_size as usize).enumerate()
+        {
+            grid[(ty * tiles_x) as usize + tx].as_mut().unwrap().rows.push(piece);
+        }
+    }
+
+    return tile_order(tiles_x, tiles_y, order).into_iter()
+        .map(|(tx, ty)| grid[(ty * tiles_x + tx) as usize].take().unwrap())
+        .collect();
+}
+
+// Renders the tiles on the global thread pool. Workers take the next tile from a shared queue, so
+// tiles start in the order they were given.
+pub fn render_tiles<T, F>(tiles: Vec<T>, render_tile: F)
+    where T: Send, F: Fn(T) + Sync
+{
+    let queue = Mutex::new(tiles.into_iter());
+
+    rayon::scope(|s|
+    {
+        for _ in 0..rayon::current_num_threads()
+        {
+            s.spawn(|_|
+            {
+                loop
+                {
+                    let tile = queue.lock().unwrap().next();
+                    match tile
+                    {
+                        Some(tile) => render_tile(tile),
+                        None => break,
+                    }
+                }
+            });
+        }
+    });
+}
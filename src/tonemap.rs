@@ -0,0 +1,188 @@
+use serde::Deserialize;
+use crate::application::Pixel;
+use crate::disney::luminance;
+use crate::color::linear_to_srgb;
+
+// Maps scene radiance to the displayable [0, 1] range.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneMapper
+{
+    // Clips everything above one.
+    Linear,
+    // Reinhard on luminance, extended so `white_point` maps to one.
+    Reinhard,
+    // Stephen Hill's fit of the ACES reference and sRGB output transforms.
+    Aces,
+    // Troy Sobotka's AgX with the default contrast look.
+    Agx,
+    // John Hable's filmic curve from Uncharted 2.
+    Hable,
+}
+
+impl std::str::FromStr for ToneMapper
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s
+        {
+            "linear" => Ok(ToneMapper::Linear),
+            "reinhard" => Ok(ToneMapper::Reinhard),
+            "aces" => Ok(ToneMapper::Aces),
+            "agx" => Ok(ToneMapper::Agx),
+            "hable" => Ok(ToneMapper::Hable),
+            _ => Err(format!("Unknown tone mapper '{}'", s)),
+        }
+    }
+}
+
+// Everything between the linear film and an 8 or 16 bit image: exposure, tone mapping and the sRGB encoding.
+#[derive(Copy, Clone, Debug)]
+pub struct DisplayTransform
+{
+    pub tone_mapper: ToneMapper,
+    // In stops, every +1 doubles the brightness.
+    pub exposure: f32,
+    // Radiance that maps to white with Reinhard.
+    pub white_point: f32,
+}
+
+impl Default for DisplayTransform
+{
+    fn default() -> Self
+    {
+        DisplayTransform { tone_mapper: ToneMapper::Linear, exposure: 0f32, white_point: 4f32 }
+    }
+}
+
+// Row major 3x3 matrix times a column vector.
+fn mul(m: &[[f32; 3]; 3], v: glm::Vec3) -> glm::Vec3
+{
+    return glm::vec3(
+        m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+        m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+        m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z);
+}
+
+fn map(v: glm::Vec3, f: impl Fn(f32) -> f32) -> glm::Vec3
+{
+    return glm::vec3(f(v.x), f(v.y), f(v.z));
+}
+
+static ACES_INPUT: [[f32; 3]; 3] =
+[
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777],
+];
+
+static ACES_OUTPUT: [[f32; 3]; 3] =
+[
+    [ 1.60475, -0.53108, -0.07367],
+    [-0.10208,  1.10813, -0.00605],
+    [-0.00327, -0.07276,  1.07602],
+];
+
+fn aces(color: glm::Vec3) -> glm::Vec3
+{
+    let v = mul(&ACES_INPUT, color);
+    let v = map(v, |x| (x * (x + 0.0245786) - 0.000090537) / (x * (0.983729 * x + 0.4329510) + 0.238081));
+    return mul(&ACES_OUTPUT, v);
+}
+
+static AGX_INSET: [[f32; 3]; 3] =
+[
+    [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+    [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+    [0.0423756549057051, 0.0784336, 0.879142973793104],
+];
+
+static AGX_OUTSET: [[f32; 3]; 3] =
+[
+    [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+    [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+    [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+];
+
+fn agx(color: glm::Vec3) -> glm::Vec3
+{
+    // Log encoding over the exposure range of the AgX base, around middle gray at 0.18.
+    let min_ev = -12.47393f32;
+    let max_ev = 4.026069f32;
+
+    let v = mul(&AGX_INSET, color);
+    let v = map(v, |x| (x.max(1e-10).log2().max(min_ev).min(max_ev) - min_ev) / (max_ev - min_ev));
+
+    // Polynomial fit of the default contrast sigmoid, its output is gamma 2.2 encoded.
+    let v = map(v, |x|
+    {
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+    });
+
+    let v = mul(&AGX_OUTSET, v);
+    return map(v, |x| x.max(0f32).powf(2.2));
+}
+
+fn hable_curve(x: f32) -> f32
+{
+    let (a, b, c, d, e, f) = (0.15f32, 0.50f32, 0.10f32, 0.20f32, 0.02f32, 0.30f32);
+    return ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f;
+}
+
+fn hable(color: glm::Vec3) -> glm::Vec3
+{
+    // The usual exposure bias of two, with a linear white point of 11.2.
+    let white_scale = 1f32 / hable_curve(11.2);
+    return map(color, |x| hable_curve(x * 2f32) * white_scale);
+}
+
+fn reinhard(color: glm::Vec3, white_point: f32) -> glm::Vec3
+{
+    let l = luminance(color);
+    if l <= 0f32
+    {
+        return glm::vec3(0f32, 0f32, 0f32);
+    }
+
+    let mapped = l * (1f32 + l / (white_point * white_point)) / (1f32 + l);
+    return color * (mapped / l);
+}
+
+impl DisplayTransform
+{
+    // Display linear color in [0, 1].
+    pub fn tone_map(&self, color: glm::Vec3) -> glm::Vec3
+    {
+        let color = color * 2f32.powf(self.exposure);
+
+        let mapped = match self.tone_mapper
+        {
+            ToneMapper::Linear => color,
+            ToneMapper::Reinhard => reinhard(color, self.white_point),
+            ToneMapper::Aces => aces(color),
+            ToneMapper::Agx => agx(color),
+            ToneMapper::Hable => hable(color),
+        };
+
+        return map(mapped, |x| x.max(0f32).min(1f32));
+    }
+
+    // Tone mapped and sRGB encoded, ready for an LDR image or the preview.
+    pub fn to_display(&self, color: glm::Vec3) -> glm::Vec3
+    {
+        return map(self.tone_map(color), linear_to_srgb);
+    }
+
+    pub fn apply(&self, pixels: &[Pixel], display: &mut [Pixel])
+    {
+        for (pixel, out) in pixels.iter().zip(display.iter_mut())
+        {
+            let color = self.to_display(glm::vec3(pixel.r, pixel.g, pixel.b));
+            *out = Pixel { r: color.x, g: color.y, b: color.z, a: pixel.a };
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use crate::model::Vertex;
+use crate::animation::{Interpolation, Track};
+
+#[derive(Copy, Clone)]
+pub struct Transform
+{
+    pub linear: glm::Mat3,
+    pub normal: glm::Mat3,
+    pub translation: glm::Vec3,
+}
+
+fn rotation_matrix(degrees: glm::Vec3) -> glm::Mat3
+{
+    let (sx, cx) = degrees.x.to_radians().sin_cos();
+    let (sy, cy) = degrees.y.to_radians().sin_cos();
+    let (sz, cz) = degrees.z.to_radians().sin_cos();
+
+    // Column major
+    let rx = glm::mat3(
+        1f32, 0f32, 0f32,
+        0f32, cx, sx,
+        0f32, -sx, cx,
+    );
+    let ry = glm::mat3(
+        cy, 0f32, -sy,
+        0f32, 1f32, 0f32,
+        sy, 0f32, cy,
+    );
+    let rz = glm::mat3(
+        cz, sz, 0f32,
+        -sz, cz, 0f32,
+        0f32, 0f32, 1f32,
+    );
+
+    return rz * ry * rx;
+}
+
+fn scale_matrix(scale: glm::Vec3) -> glm::Mat3
+{
+    return glm::mat3(
+        scale.x, 0f32, 0f32,
+        0f32, scale.y, 0f32,
+        0f32, 0f32, scale.z,
+    );
+}
+
+impl Transform
+{
+    // Scale, then rotate (euler angles in degrees, applied X, Y, Z), then translate.
+    pub fn from_trs(translation: glm::Vec3, rotation: glm::Vec3, scale: glm::Vec3) -> Transform
+    {
+        let rotation = rotation_matrix(rotation);
+        let inv_scale = glm::vec3(1f32 / scale.x, 1f32 / scale.y, 1f32 / scale.z);
+
+        Transform
+        {
+            linear: rotation * scale_matrix(scale),
+            normal: rotation * scale_matrix(inv_scale),
+            translation,
+        }
+    }
+
+    pub fn point(&self, p: glm::Vec3) -> glm::Vec3
+    {
+        return self.linear * p + self.translation;
+    }
+
+    pub fn vector(&self, v: glm::Vec3) -> glm::Vec3
+    {
+        return self.linear * v;
+    }
+
+    pub fn normal(&self, n: glm::Vec3) -> glm::Vec3
+    {
+        return glm::normalize(self.normal * n);
+    }
+
+    pub fn vertex(&self, v: &Vertex) -> Vertex
+    {
+        Vertex
+        {
+            pos: self.point(v.pos),
+            normal: self.normal(v.normal),
+            tangent: glm::normalize(self.vector(v.tangent)),
+            bitangent: glm::normalize(self.vector(v.bitangent)),
+            uv: v.uv,
+        }
+    }
+}
+
+// Rotation per step when bounding a motion, small enough that the padding stays tight.
+static BOUNDS_STEP: f32 = 0.1f32;
+// Minimum steps per key interval for curved tracks.
+static CURVE_STEPS: u32 = 8;
+
+// Keyframed translation, rotation (euler angles in degrees) and scale, each its own track.
+pub struct MotionTransform
+{
+    pub translation: Track<glm::Vec3>,
+    pub rotation: Track<glm::Vec3>,
+    pub scale: Track<glm::Vec3>,
+}
+
+impl MotionTransform
+{
+    pub fn is_animated(&self) -> bool
+    {
+        return self.translation.is_animated() || self.rotation.is_animated() || self.scale.is_animated();
+    }
+
+    pub fn at(&self, time: f32) -> Transform
+    {
+        return Transform::from_trs(self.translation.sample(time), self.rotation.sample(time), self.scale.sample(time));
+    }
+
+    // An odd number of negative scale axes mirrors the object, which turns the winding of its triangles around.
+    // Scale keys can't change sign, so this holds over the whole motion.
+    pub fn is_mirrored(&self) -> bool
+    {
+        let scale = self.scale.sample(0f32);
+        return scale.x * scale.y * scale.z < 0f32;
+    }
+
+    // Grows the bounds by everywhere the object space point `p` passes through between `open` and `close`.
+    // The motion is sampled at every key and finely enough in between for its rotation, then padded by the
+    // largest second difference of the samples, which covers how far the path bulges out between two samples.
+    pub fn bounds(&self, p: glm::Vec3, open: f32, close: f32, min: &mut glm::Vec3, max: &mut glm::Vec3)
+    {
+        let tracks = [&self.translation, &self.rotation, &self.scale];
+
+        let mut times = vec![open, close];
+        for track in &tracks
+        {
+            times.extend(track.times().filter(|&time| time > open && time < close));
+        }
+        times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        times.dedup();
+
+        let curved = tracks.iter().any(|track| track.is_animated() && track.interpolation != Interpolation::Linear);
+
+        let mut samples = vec![self.at(open).point(p)];
+        for pair in times.windows(2)
+        {
+            // The euler angle changes add up to at least the angle of the combined rotation.
+            let delta = self.rotation.sample(pair[1]) - self.rotation.sample(pair[0]);
+            let turn = (delta.x.abs() + delta.y.abs() + delta.z.abs()).to_radians();
+            let mut steps = (turn / BOUNDS_STEP).ceil().max(1f32) as u32;
+            if curved
+            {
+                steps = steps.max(CURVE_STEPS);
+            }
+
+            for step in 1..=steps
+            {
+                let time = pair[0] + (pair[1] - pair[0]) * step as f32 / steps as f32;
+                samples.push(self.at(time).point(p));
+            }
+        }
+
+        let mut pad = 0f32;
+        for window in samples.windows(3)
+        {
+            pad = pad.max(glm::length(window[0] - window[1] * 2f32 + window[2]) * 0.25f32);
+        }
+
+        let pad = glm::vec3(pad, pad, pad);
+        for q in &samples
+        {
+            *min = glm::min(*min, *q - pad);
+            *max = glm::max(*max, *q + pad);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn scaled(scale: Track<glm::Vec3>) -> MotionTransform
+    {
+        return MotionTransform { translation: Track::constant(glm::vec3(0f32, 0f32, 0f32)), rotation: Track::constant(glm::vec3(0f32, 30f32, 0f32)), scale };
+    }
+
+    #[test]
+    fn odd_negative_scales_mirror()
+    {
+        assert!(!scaled(Track::constant(glm::vec3(1f32, 2f32, 3f32))).is_mirrored());
+        assert!(scaled(Track::constant(glm::vec3(-1f32, 2f32, 3f32))).is_mirrored());
+        assert!(!scaled(Track::constant(glm::vec3(-1f32, -2f32, 3f32))).is_mirrored());
+        assert!(scaled(Track::new(vec![(0f32, glm::vec3(1f32, 1f32, -1f32)), (1f32, glm::vec3(2f32, 2f32, -2f32))], Interpolation::Linear)).is_mirrored());
+    }
+
+    #[test]
+    fn mirroring_reverses_the_winding()
+    {
+        // Wound so the face points along its vertex normal.
+        let (p0, p1, p2) = (glm::vec3(0f32, 0f32, 1f32), glm::vec3(1f32, 0f32, 1f32), glm::vec3(0f32, 1f32, 1f32));
+        let facing = |transform: &Transform, p0: glm::Vec3, p1: glm::Vec3, p2: glm::Vec3|
+        {
+            let (q0, q1, q2) = (transform.point(p0), transform.point(p1), transform.point(p2));
+            return glm::dot(glm::cross(q1 - q0, q2 - q0), transform.normal(glm::vec3(0f32, 0f32, 1f32)));
+        };
+
+        let plain = scaled(Track::constant(glm::vec3(1f32, 2f32, 1f32))).at(0f32);
+        assert!(facing(&plain, p0, p1, p2) > 0f32);
+
+        let mirrored = scaled(Track::constant(glm::vec3(1f32, 2f32, -1f32))).at(0f32);
+        assert!(facing(&mirrored, p0, p1, p2) < 0f32);
+        assert!(facing(&mirrored, p0, p2, p1) > 0f32);
+    }
+}
@@ -2,29 +2,91 @@ use crate::shape::*;
 use bvh::aabb::{AABB, Bounded};
 use bvh::bounding_hierarchy::{BHShape};
 use crate::model::Vertex;
+use crate::transform::MotionTransform;
+use std::borrow::Cow;
+use std::sync::Arc;
 
+#[derive(Clone)]
 pub struct Triangle
 {
     pub v0: Vertex,
     pub v1: Vertex,
     pub v2: Vertex,
     pub material_id: u32,
+    // Index in the scene's triangle list.
+    pub primitive_id: u32,
     pub node_index: usize,
+    // Moving triangles keep their vertices in object space.
+    pub motion: Option<Arc<MotionTransform>>,
+    // Bounds of a moving triangle over the shutter interval, set by `update_bounds`.
+    pub motion_bounds: Option<(glm::Vec3, glm::Vec3)>,
+}
+
+impl Triangle
+{
+    // The triangle where it is at `time`.
+    pub fn at_time(&self, time: f32) -> Cow<'_, Triangle>
+    {
+        return match &self.motion
+        {
+            None => Cow::Borrowed(self),
+            Some(motion) =>
+            {
+                let transform = motion.at(time);
+                Cow::Owned(Triangle
+                {
+                    v0: transform.vertex(&self.v0),
+                    v1: transform.vertex(&self.v1),
+                    v2: transform.vertex(&self.v2),
+                    material_id: self.material_id,
+                    primitive_id: self.primitive_id,
+                    node_index: self.node_index,
+                    motion: None,
+                    motion_bounds: None,
+                })
+            },
+        };
+    }
+
+    // Bounds a moving triangle over the given time interval.
+    pub fn update_bounds(&mut self, open: f32, close: f32)
+    {
+        if let Some(motion) = &self.motion
+        {
+            let mut min = glm::vec3(std::f32::MAX, std::f32::MAX, std::f32::MAX);
+            let mut max = glm::vec3(-std::f32::MAX, -std::f32::MAX, -std::f32::MAX);
+            for v in &[self.v0.pos, self.v1.pos, self.v2.pos]
+            {
+                motion.bounds(*v, open, close, &mut min, &mut max);
+            }
+
+            self.motion_bounds = Some((min, max));
+        }
+    }
 }
 
 impl Bounded for Triangle
 {
     fn aabb(&self) -> AABB
     {
-        let mut min = glm::vec3(std::f32::MAX, std::f32::MAX, std::f32::MAX);
-        min = glm::min(min, self.v0.pos);
-        min = glm::min(min, self.v1.pos);
-        min = glm::min(min, self.v2.pos);
-
-        let mut max = glm::vec3(-std::f32::MAX, -std::f32::MAX, -std::f32::MAX);
-        max = glm::max(max, self.v0.pos);
-        max = glm::max(max, self.v1.pos);
-        max = glm::max(max, self.v2.pos);
+        let (min, max) = match self.motion_bounds
+        {
+            Some(bounds) => bounds,
+            None =>
+            {
+                let mut min = glm::vec3(std::f32::MAX, std::f32::MAX, std::f32::MAX);
+                min = glm::min(min, self.v0.pos);
+                min = glm::min(min, self.v1.pos);
+                min = glm::min(min, self.v2.pos);
+
+                let mut max = glm::vec3(-std::f32::MAX, -std::f32::MAX, -std::f32::MAX);
+                max = glm::max(max, self.v0.pos);
+                max = glm::max(max, self.v1.pos);
+                max = glm::max(max, self.v2.pos);
+
+                (min, max)
+            },
+        };
 
         AABB::with_bounds(bvh::nalgebra::Point3::new(min.x, min.y, min.z), bvh::nalgebra::Point3::new(max.x, max.y, max.z))
     }
@@ -86,11 +148,21 @@ impl Shape for Triangle {
         return glm::normalize((self.v0.normal * (1f32 - barry.x - barry.y)) + (self.v1.normal * barry.x) + ( self.v2.normal * barry.y));
     }
 
+    fn get_geometric_normal(&self) -> glm::Vec3
+    {
+        return glm::normalize(glm::cross(self.v1.pos - self.v0.pos, self.v2.pos - self.v0.pos));
+    }
+
     fn get_material_id(&self) -> u32
     {
         return self.material_id;
     }
 
+    fn get_primitive_id(&self) -> u32
+    {
+        return self.primitive_id;
+    }
+
     fn get_tangents(&self, _normal: glm::Vec3, tangent: &mut glm::Vec3, bitangent: &mut glm::Vec3, barry: glm::Vec2)
     {
         *tangent = glm::normalize((self.v0.tangent * (1f32 - barry.x - barry.y)) + (self.v1.tangent * barry.x) + ( self.v2.tangent * barry.y));
@@ -0,0 +1,345 @@
+extern crate sdl2;
+extern crate gl;
+
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Scancode};
+use std::time::Instant;
+use crate::application::*;
+
+// Scene units per second the fly camera moves, unless set with `fly_speed`.
+pub static DEFAULT_FLY_SPEED: f32 = 2f32;
+// Speed multiplier while shift is held.
+static FLY_BOOST: f32 = 4f32;
+// Radians per pixel of mouse movement.
+static MOUSE_SENSITIVITY: f32 = 0.003f32;
+
+macro_rules! TRY_D
+{
+    ($e: expr) =>
+    {
+        match $e
+        {
+            Ok(_) => (),
+            Err(error) => println!("{}", error),
+        }
+    }
+}
+
+pub struct Window
+{
+    pub sdl_context: sdl2::Sdl,
+    pub video_subsystem: sdl2::VideoSubsystem,
+    pub window: sdl2::video::Window,
+    pub back_buffer_id: u32,
+    // Framebuffer with the back buffer texture attached, the blit reads from it.
+    pub upload_frame_buffer: u32,
+}
+
+pub struct AppBuilder
+{
+    title: String,
+    // Window size, the back buffer is scaled to fill it.
+    width: u32,
+    height: u32,
+    render_width: u32,
+    render_height: u32,
+    // The window changed size since the last frame.
+    resized: bool,
+    running: bool,
+    // Keep showing the result after the render finished instead of closing the window.
+    keep_open: bool,
+    fly_speed: f32,
+}
+
+impl AppBuilder
+{
+    pub fn new(title: &str, width: u32, height: u32) -> AppBuilder
+    {
+        AppBuilder
+        {
+            title: title.to_owned(),
+            width,
+            height,
+            render_width: width,
+            render_height: height,
+            resized: false,
+            running: false,
+            keep_open: false,
+            fly_speed: DEFAULT_FLY_SPEED,
+        }
+    }
+
+    pub fn keep_open(&mut self, keep_open: bool) -> &mut AppBuilder
+    {
+        self.keep_open = keep_open;
+        return self;
+    }
+
+    // Size of the back buffer at the start, the window size by default.
+    pub fn render_size(&mut self, width: u32, height: u32) -> &mut AppBuilder
+    {
+        self.render_width = width;
+        self.render_height = height;
+        return self;
+    }
+
+    pub fn fly_speed(&mut self, fly_speed: f32) -> &mut AppBuilder
+    {
+        self.fly_speed = fly_speed;
+        return self;
+    }
+
+    // Returns the exit status reported by the renderer.
+    pub fn start(&mut self, renderer_trait: &mut dyn Renderer) -> i32
+    {
+        let sdl_context = sdl2::init().unwrap();
+        let video_subsystem = sdl_context.video().unwrap();
+        let window = video_subsystem
+            .window(&self.title, self.width, self.height)
+            .hidden()
+            .opengl()
+            .resizable()
+            .build()
+            .unwrap();
+
+        // Init OpenGL
+        let _gl_context = window.gl_create_context().unwrap();
+        gl::load_with(|s| video_subsystem.gl_get_proc_address(s) as *const std::os::raw::c_void);
+
+        // Disable VSync
+        TRY_D!(video_subsystem.gl_set_swap_interval(0));
+
+        let mut event_pump = sdl_context.event_pump().unwrap();
+
+        /*
+        Create Upload Frame Buffer
+        */
+        let back_buffer = RenderTexture::new(self.render_width, self.render_height);
+        let back_buffer_id = self.create_texture(&back_buffer);
+        let mut upload_frame_buffer: u32 = 0;
+        self.create_fb(&mut upload_frame_buffer);
+        self.attach_texture(upload_frame_buffer, back_buffer_id);
+
+        // Tone mapped copy of the back buffer that is uploaded to the texture.
+        let mut display_buffer = RenderTexture::new(self.render_width, self.render_height);
+
+        let mut window = Window { sdl_context, video_subsystem, window, back_buffer_id, upload_frame_buffer };
+        let mut app = Application { back_buffer };
+
+        renderer_trait.init(&mut app);
+
+        self.show(&mut window);
+
+        let mut status = None;
+        let mut last_frame = Instant::now();
+
+        self.running = true;
+        while self.running
+        {
+            let frame_time = last_frame.elapsed().as_secs_f32();
+            last_frame = Instant::now();
+            let input = self.parse_events(&mut event_pump, frame_time);
+
+            // The preview is just a view on the offline loop, the render ends on the same conditions.
+            if status.is_none()
+            {
+                // A finished render is only stretched to the new window size.
+                if self.resized
+                {
+                    renderer_trait.resize(&mut app, self.width, self.height);
+                }
+
+                if !input.is_empty()
+                {
+                    renderer_trait.move_camera(&mut app, &input);
+                }
+
+                renderer_trait.render(&mut app);
+
+                if let Some(reason) = renderer_trait.stop_reason()
+                {
+                    status = Some(renderer_trait.finish(&mut app, reason));
+                    self.running &= self.keep_open;
+                }
+            }
+            else
+            {
+                std::thread::sleep(std::time::Duration::from_millis(16));
+            }
+
+            // The renderer reallocates the back buffer on resizes and when it moves on from the preview to the output resolution.
+            if display_buffer.width != app.back_buffer.width || display_buffer.height != app.back_buffer.height
+            {
+                display_buffer = RenderTexture::new(app.back_buffer.width, app.back_buffer.height);
+                self.delete_texture(window.back_buffer_id);
+                window.back_buffer_id = self.create_texture(&app.back_buffer);
+                self.attach_texture(window.upload_frame_buffer, window.back_buffer_id);
+            }
+
+            renderer_trait.display_transform().apply(&app.back_buffer.pixels, &mut display_buffer.pixels);
+            self.update_back_buffer(window.back_buffer_id, &display_buffer);
+            self.copy_back_to_front(window.upload_frame_buffer, &display_buffer);
+
+            window.window.gl_swap_window();
+        }
+
+        // Closing the window early still writes what has been rendered so far.
+        return match status
+        {
+            Some(status) => status,
+            None => renderer_trait.finish(&mut app, StopReason::Cancelled),
+        };
+    }
+
+    // `frame_buffer` has the back buffer texture attached, it is stretched over the window's back buffer.
+    fn copy_back_to_front(&self, frame_buffer: u32, texture: &RenderTexture)
+    {
+        unsafe
+        {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, frame_buffer);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, 0);
+
+            gl::BlitFramebuffer(0, 0, texture.width as i32, texture.height as i32,
+                                0, 0, self.width as i32, self.height as i32,
+                                gl::COLOR_BUFFER_BIT, gl::NEAREST);
+
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        }
+    }
+
+    // Needs to be redone whenever the texture is recreated.
+    fn attach_texture(&self, frame_buffer: u32, texture_id: u32)
+    {
+        unsafe
+        {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, frame_buffer);
+            gl::FramebufferTexture2D(gl::READ_FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, texture_id, 0);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        }
+    }
+
+    fn update_back_buffer(&self, id: u32, texture: &RenderTexture)
+    {
+        unsafe
+        {
+            gl::BindTexture(gl::TEXTURE_2D, id);
+            gl::TexSubImage2D(gl::TEXTURE_2D, 0, 0, 0, texture.width as i32, texture.height as i32,
+                              gl::BGRA, gl::FLOAT, texture.pixels.as_ptr() as *const std::os::raw::c_void);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+    }
+
+    fn create_fb(&self, id: &mut u32)
+    {
+        unsafe
+        {
+            gl::GenFramebuffers(1, id);
+        }
+    }
+
+    fn delete_texture(&self, id: u32)
+    {
+        unsafe
+        {
+            gl::DeleteTextures(1, &id);
+        }
+    }
+
+    fn create_texture(&self, texture: &RenderTexture) -> u32
+    {
+        let mut id: u32 = 0;
+
+        unsafe
+        {
+            gl::GenTextures(1, &mut id);
+
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, id);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA as i32, texture.width as i32, texture.height as i32,
+                           0, gl::BGRA, gl::FLOAT, texture.pixels.as_ptr() as *const std::os::raw::c_void);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+
+        return id;
+    }
+
+    // Handles the window events and returns the fly camera movement: WASD to move, Q and E down and up, shift to
+    // move faster, the right mouse button to look around, the wheel to zoom and ctrl with the wheel to focus.
+    fn parse_events(&mut self, event_pump: &mut sdl2::EventPump, frame_time: f32) -> CameraInput
+    {
+        use sdl2::event::WindowEvent;
+
+        let mut input = CameraInput::default();
+        let mut scroll = 0f32;
+        self.resized = false;
+
+        for event in event_pump.poll_iter()
+        {
+            match event
+            {
+                Event::Quit {..} |
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } =>
+                {
+                    self.running = false;
+                },
+                Event::Window { win_event: WindowEvent::Resized(width, height), ..} =>
+                {
+                    self.resize_event(width, height);
+                },
+                Event::MouseMotion { mousestate, xrel, yrel, .. } if mousestate.right() =>
+                {
+                    input.yaw -= xrel as f32 * MOUSE_SENSITIVITY;
+                    input.pitch -= yrel as f32 * MOUSE_SENSITIVITY;
+                },
+                Event::MouseWheel { y, .. } =>
+                {
+                    scroll += y as f32;
+                },
+                _ => {}
+            }
+        }
+
+        let keys = event_pump.keyboard_state();
+        let pressed = |key: Scancode| keys.is_scancode_pressed(key) as i32 as f32;
+        let direction = glm::vec3(pressed(Scancode::D) - pressed(Scancode::A), pressed(Scancode::E) - pressed(Scancode::Q), pressed(Scancode::W) - pressed(Scancode::S));
+        let speed = if keys.is_scancode_pressed(Scancode::LShift) { self.fly_speed * FLY_BOOST } else { self.fly_speed };
+        input.translation = direction * (speed * frame_time);
+
+        if keys.is_scancode_pressed(Scancode::LCtrl) || keys.is_scancode_pressed(Scancode::RCtrl)
+        {
+            input.focus = scroll;
+        }
+        else
+        {
+            input.zoom = scroll;
+        }
+
+        return input;
+    }
+
+    fn resize_event(&mut self, width: i32, height: i32)
+    {
+        if width > 0 && height > 0
+        {
+            self.width = width as u32;
+            self.height = height as u32;
+            self.resized = true;
+        }
+    }
+
+    fn clear(&self)
+    {
+        unsafe
+        {
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+            gl::ClearColor(0.3, 0.3, 0.5, 1.0);
+        }
+    }
+
+    fn show(&self, window: &mut Window)
+    {
+        self.clear();
+        window.window.gl_swap_window();
+        window.window.show();
+    }
+}